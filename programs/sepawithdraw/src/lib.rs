@@ -1,30 +1,418 @@
 #![allow(unused)]
 use anchor_lang::prelude::*;
-use anchor_lang::solana_program::clock;
+use anchor_lang::solana_program::{clock, keccak};
+use anchor_lang::system_program;
 use anchor_spl::{
     associated_token::AssociatedToken,
-    token::{self, Mint, TokenAccount, Transfer},
+    token::{self, Burn, Mint, TokenAccount, Transfer},
 };
 declare_id!("C1dGXHWZ1TyFQjkfQcqjsckcYuhak63X4PCn2rkXkMGL");
 
+const STATE_SEED: &[u8] = b"state";
 const PUB_POOL_SEEDS: &[u8] = b"public_pool";
 const RES_POOL_SEEDS: &[u8] = b"reserve_pool";
+const BUR_POOL_SEEDS: &[u8] = b"burn_pool";
+const VAULT_SEEDS: &[u8] = b"payment_vault";
+const SOL_VAULT_SEEDS: &[u8] = b"sol_vault";
+const PAYMENT_MINT_SEED: &[u8] = b"payment_mint";
+const WITHDRAWAL_SEED: &[u8] = b"withdrawal";
+const ALLOCATION_SEED: &[u8] = b"allocation";
 const VEST_SEED: &[u8] = b"vesting";
+const ROUND_SEED: &[u8] = b"round";
+
+pub const MAX_PURCHASES: usize = 100;
+pub const MAX_PAYMENT_MINTS: usize = 4;
+/// Decimals of the micro-USD unit that oracle-priced rounds are quoted in.
+pub const USD_DECIMALS: i32 = 6;
+
+/// Pyth oracle program that owns every price account the sale will read.
+pub mod pyth_program {
+    use super::*;
+
+    #[cfg(not(feature = "devnet"))]
+    declare_id!("FsJ3A3u2vn5cTVofAjvy6y5kwABJAqYWpe4975bi2epH");
+    #[cfg(feature = "devnet")]
+    declare_id!("gSbePebfvPy7tRqimPoVecS2UsBvYv46ynrzWocc92s");
+}
+
+// Pyth v2 price account layout
+const PYTH_MAGIC: u32 = 0xa1b2c3d4;
+const PYTH_PRICE_ACCOUNT_TYPE: u32 = 3;
+const PYTH_STATUS_TRADING: u32 = 1;
+const PYTH_PRICE_ACCOUNT_LEN: usize = 3312;
+
+pub const BPS_DENOMINATOR: u64 = 10_000;
+
+// `State.paused` flags
+pub const PAUSE_PURCHASES: u8 = 1 << 0;
+pub const PAUSE_CLAIMS: u8 = 1 << 1;
+pub const PAUSE_REFUNDS: u8 = 1 << 2;
+pub const PAUSE_ALL: u8 = PAUSE_PURCHASES | PAUSE_CLAIMS | PAUSE_REFUNDS;
 
 #[program]
 pub mod sepawithdraw {
     use super::*;
 
-    pub fn initialize(ctx: Context<Initialize>) -> Result<()> {
+    pub fn initialize(
+        ctx: Context<Initialize>,
+        sale_id: u64,
+        public_pool_bps: u16,
+        burn_pool_bps: u16,
+        soft_cap: u64,
+        hard_cap: u64,
+        withdrawal_delay: i64,
+    ) -> Result<()> {
+        require!(
+            public_pool_bps as u64 + burn_pool_bps as u64 <= BPS_DENOMINATOR,
+            CustomError::InvalidAllocation
+        );
+        require!(
+            hard_cap == 0 || hard_cap >= soft_cap,
+            CustomError::InvalidCap
+        );
+        require!(withdrawal_delay >= 0, CustomError::InvalidWithdrawalDelay);
+
         let state = &mut ctx.accounts.state;
+        state.sale_id = sale_id;
+        state.bump = ctx.bumps["state"];
         state.admin = ctx.accounts.admin.key();
         state.mint = ctx.accounts.mint.key();
         state.payment_mint = ctx.accounts.payment_mint.key();
         state.pub_supply = 0;
         state.reserve_supply = 0;
+        state.public_pool_bps = public_pool_bps;
+        state.burn_pool_bps = burn_pool_bps;
+        state.round_count = 0;
         state.current_active_phase = u8::MAX;
-        msg!("PDA initialized");
-        msg!("Admin set to: {}", state.admin);
+        state.status = SaleStatus::Active;
+        state.soft_cap = soft_cap;
+        state.hard_cap = hard_cap;
+        state.withdrawal_delay = withdrawal_delay;
+
+        // The sale's payment mint is the first accepted mint and the unit caps are quoted in
+        let payment_mint_info = &mut ctx.accounts.payment_mint_info;
+        payment_mint_info.mint = ctx.accounts.payment_mint.key();
+        payment_mint_info.decimals = ctx.accounts.payment_mint.decimals;
+        payment_mint_info.index = 0;
+        state.payment_mint_count = 1;
+
+        emit!(SaleInitialized {
+            sale_id,
+            admin: state.admin,
+            mint: state.mint,
+            payment_mint: state.payment_mint,
+            public_pool_bps,
+            burn_pool_bps,
+            soft_cap,
+            hard_cap,
+        });
+        Ok(())
+    }
+
+    pub fn propose_admin(ctx: Context<ProposeAdmin>, new_admin: Pubkey) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+
+        require!(
+            ctx.accounts.admin.key() == state.admin,
+            CustomError::Unauthorized
+        );
+
+        // Proposing the default key cancels a pending transfer
+        state.pending_admin = new_admin;
+
+        emit!(AdminProposed {
+            sale_id: state.sale_id,
+            admin: state.admin,
+            pending_admin: new_admin,
+        });
+        Ok(())
+    }
+
+    pub fn accept_admin(ctx: Context<AcceptAdmin>) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+
+        require!(
+            state.pending_admin != Pubkey::default()
+                && ctx.accounts.new_admin.key() == state.pending_admin,
+            CustomError::Unauthorized
+        );
+
+        let previous_admin = state.admin;
+        state.admin = state.pending_admin;
+        state.pending_admin = Pubkey::default();
+
+        emit!(AdminTransferred {
+            sale_id: state.sale_id,
+            previous_admin,
+            new_admin: state.admin,
+        });
+        Ok(())
+    }
+
+    pub fn grant_role(ctx: Context<SetRole>, role: Role, account: Pubkey) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+
+        require!(
+            ctx.accounts.admin.key() == state.admin,
+            CustomError::Unauthorized
+        );
+
+        *state.role_mut(role) = account;
+
+        emit!(RoleGranted {
+            sale_id: state.sale_id,
+            role,
+            account,
+        });
+        Ok(())
+    }
+
+    pub fn revoke_role(ctx: Context<SetRole>, role: Role) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+
+        require!(
+            ctx.accounts.admin.key() == state.admin,
+            CustomError::Unauthorized
+        );
+
+        let account = std::mem::take(state.role_mut(role));
+
+        emit!(RoleRevoked {
+            sale_id: state.sale_id,
+            role,
+            account,
+        });
+        Ok(())
+    }
+
+    pub fn set_paused(ctx: Context<SetPaused>, paused: u8) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+
+        require!(
+            state.has_role(Role::Pauser, &ctx.accounts.authority.key()),
+            CustomError::Unauthorized
+        );
+        require!(paused & !PAUSE_ALL == 0, CustomError::InvalidPauseFlags);
+
+        let previous = state.paused;
+        state.paused = paused;
+
+        emit!(PauseToggled {
+            sale_id: state.sale_id,
+            authority: ctx.accounts.authority.key(),
+            previous,
+            paused,
+        });
+        Ok(())
+    }
+
+    pub fn add_payment_mint(ctx: Context<AddPaymentMint>) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+
+        require!(
+            state.has_role(Role::Operator, &ctx.accounts.authority.key()),
+            CustomError::Unauthorized
+        );
+        require!(
+            (state.payment_mint_count as usize) < MAX_PAYMENT_MINTS,
+            CustomError::TooManyPaymentMints
+        );
+
+        let payment_mint_info = &mut ctx.accounts.payment_mint_info;
+        payment_mint_info.mint = ctx.accounts.payment_mint.key();
+        payment_mint_info.decimals = ctx.accounts.payment_mint.decimals;
+        payment_mint_info.index = state.payment_mint_count;
+        state.payment_mint_count += 1;
+
+        emit!(PaymentMintAdded {
+            sale_id: state.sale_id,
+            mint: payment_mint_info.mint,
+            index: payment_mint_info.index,
+            decimals: payment_mint_info.decimals,
+        });
+        Ok(())
+    }
+
+    pub fn set_mint_price(ctx: Context<SetMintPrice>, round: u8, price: Price) -> Result<()> {
+        let clock = clock::Clock::get()?;
+        let round_account = &mut ctx.accounts.round_account;
+        let index = ctx.accounts.payment_mint_info.index;
+
+        require!(
+            ctx.accounts.state.has_role(Role::Operator, &ctx.accounts.authority.key()),
+            CustomError::Unauthorized
+        );
+        // The sale's own payment mint is priced by the round's reference price
+        require!(index > 0, CustomError::InvalidPaymentMint);
+        require!(
+            price.numerator == 0 || price.denominator > 0,
+            CustomError::InvalidPrice
+        );
+        if clock.unix_timestamp > round_account.end_time {
+            return Err(CustomError::RoundExpired.into());
+        }
+
+        round_account.mint_prices[index as usize] = price;
+
+        emit!(MintPriceSet {
+            sale_id: ctx.accounts.state.sale_id,
+            round,
+            mint: ctx.accounts.payment_mint_info.mint,
+            price,
+        });
+        Ok(())
+    }
+
+    pub fn set_price_feed(
+        ctx: Context<SetPriceFeed>,
+        max_price_age: i64,
+        max_confidence_bps: u16,
+    ) -> Result<()> {
+        let clock = clock::Clock::get()?;
+        let payment_mint_info = &mut ctx.accounts.payment_mint_info;
+
+        require!(
+            ctx.accounts.state.has_role(Role::Operator, &ctx.accounts.authority.key()),
+            CustomError::Unauthorized
+        );
+
+        // Without a feed the mint falls back to the round's fixed mint price
+        match &ctx.accounts.price_feed {
+            Some(price_feed) => {
+                require!(
+                    max_price_age > 0 && max_confidence_bps as u64 <= BPS_DENOMINATOR,
+                    CustomError::InvalidOracleConfig
+                );
+                payment_mint_info.price_feed = price_feed.key();
+                payment_mint_info.max_price_age = max_price_age;
+                payment_mint_info.max_confidence_bps = max_confidence_bps;
+                // Reject feeds that can't be read before anyone buys against them
+                read_price_feed(price_feed, payment_mint_info, clock.unix_timestamp)?;
+            }
+            None => {
+                payment_mint_info.price_feed = Pubkey::default();
+                payment_mint_info.max_price_age = 0;
+                payment_mint_info.max_confidence_bps = 0;
+            }
+        }
+
+        emit!(PriceFeedSet {
+            sale_id: ctx.accounts.state.sale_id,
+            mint: payment_mint_info.mint,
+            price_feed: payment_mint_info.price_feed,
+            max_price_age: payment_mint_info.max_price_age,
+            max_confidence_bps: payment_mint_info.max_confidence_bps,
+        });
+        Ok(())
+    }
+
+    pub fn add_round(ctx: Context<AddRound>, config: RoundConfig) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+
+        require!(
+            state.has_role(Role::Operator, &ctx.accounts.authority.key()),
+            CustomError::Unauthorized
+        );
+        require!(state.round_count < u8::MAX, CustomError::InvalidRoundConfig);
+        require!(
+            config.price.numerator > 0 && config.price.denominator > 0,
+            CustomError::InvalidPrice
+        );
+        require!(
+            config.sol_price.numerator == 0 || config.sol_price.denominator > 0,
+            CustomError::InvalidPrice
+        );
+        require!(
+            config.usd_price.numerator == 0 || config.usd_price.denominator > 0,
+            CustomError::InvalidPrice
+        );
+        require!(
+            config.allocation_bps > 0 && config.start_time < config.end_time,
+            CustomError::InvalidRoundConfig
+        );
+        require!(
+            config.max_contribution == 0 || config.max_contribution >= config.min_contribution,
+            CustomError::InvalidRoundConfig
+        );
+        config.schedule.validate()?;
+
+        // Rounds run one after another, so each starts no earlier than the last one ends
+        if state.round_count > 0 {
+            let previous_round = ctx
+                .accounts
+                .previous_round
+                .as_ref()
+                .ok_or(CustomError::InvalidRound)?;
+            require!(
+                config.start_time >= previous_round.end_time,
+                CustomError::InvalidRoundConfig
+            );
+        }
+
+        // Round allocations are shares of the public pool and may not exceed all of it
+        let allocated_bps = state
+            .allocated_bps
+            .checked_add(config.allocation_bps)
+            .ok_or(CustomError::MathOverflow)?;
+        require!(
+            allocated_bps as u64 <= BPS_DENOMINATOR,
+            CustomError::InvalidAllocation
+        );
+
+        let round = &mut ctx.accounts.round;
+        round.index = state.round_count;
+        round.allocation_bps = config.allocation_bps;
+        round.price = config.price;
+        round.mint_prices[0] = config.price;
+        round.usd_price = config.usd_price;
+        round.sol_price = config.sol_price;
+        round.schedule = config.schedule;
+        round.start_time = config.start_time;
+        round.end_time = config.end_time;
+        round.wallet_cap = config.wallet_cap;
+        round.allowlist_root = config.allowlist_root;
+        round.min_contribution = config.min_contribution;
+        round.max_contribution = config.max_contribution;
+
+        state.allocated_bps = allocated_bps;
+        state.round_count = state
+            .round_count
+            .checked_add(1)
+            .ok_or(CustomError::MathOverflow)?;
+
+        emit!(RoundAdded {
+            sale_id: state.sale_id,
+            round: round.index,
+            allocation_bps: round.allocation_bps,
+            start_time: round.start_time,
+            end_time: round.end_time,
+        });
+        Ok(())
+    }
+
+    pub fn set_allowlist_root(
+        ctx: Context<SetAllowlistRoot>,
+        round: u8,
+        allowlist_root: [u8; 32],
+    ) -> Result<()> {
+        let clock = clock::Clock::get()?;
+        let round_account = &mut ctx.accounts.round_account;
+
+        require!(
+            ctx.accounts.state.has_role(Role::Operator, &ctx.accounts.authority.key()),
+            CustomError::Unauthorized
+        );
+        if clock.unix_timestamp > round_account.end_time {
+            return Err(CustomError::RoundExpired.into());
+        }
+
+        round_account.allowlist_root = allowlist_root;
+
+        emit!(AllowlistRootSet {
+            sale_id: ctx.accounts.state.sale_id,
+            round,
+            allowlist_root,
+        });
         Ok(())
     }
 
@@ -32,22 +420,33 @@ pub mod sepawithdraw {
         let token_program = ctx.accounts.token_program.to_account_info();
         let admin = ctx.accounts.admin.to_account_info();
         let owner_ata = ctx.accounts.admin_ata.to_account_info();
-        let mut totalamount = totalsupply;
 
         let pubsup_ata = ctx.accounts.pubsup_ata.to_account_info();
         let reserve_ata = ctx.accounts.reserve_pool_ata.to_account_info();
         let state = &mut ctx.accounts.state;
 
-        // Distribute 50% each to public and reserve pools
-        let pub_supply = (totalamount.checked_mul(50).unwrap())
-            .checked_div(100)
-            .unwrap();
-        let reserve_supply = (totalamount.checked_mul(50).unwrap())
-            .checked_div(100)
-            .unwrap();
+        require!(
+            ctx.accounts.admin.key() == state.admin,
+            CustomError::Unauthorized
+        );
+        require!(
+            state.allocated_bps as u64 == BPS_DENOMINATOR,
+            CustomError::InvalidAllocation
+        );
+        require!(state.round_supply == 0, CustomError::AlreadyDistributed);
+
+        // Split the supply between the public, burn and reserve pools
+        let pub_supply = bps_of(totalsupply, state.public_pool_bps)?;
+        let burn_supply = bps_of(totalsupply, state.burn_pool_bps)?;
+        let reserve_supply = totalsupply
+            .checked_sub(pub_supply)
+            .and_then(|v| v.checked_sub(burn_supply))
+            .ok_or(CustomError::MathOverflow)?;
 
         state.pub_supply = pub_supply;
         state.reserve_supply = reserve_supply;
+        state.burn_supply = burn_supply;
+        state.round_supply = pub_supply;
 
         // Transfer public supply to public supply ATA
         let instruction = anchor_spl::token::Transfer {
@@ -59,8 +458,6 @@ pub mod sepawithdraw {
             CpiContext::new(token_program.to_account_info(), instruction),
             pub_supply,
         )?;
-        totalamount -= pub_supply;
-        msg!("Public supply transfer done...");
 
         // Transfer reserve supply to reserve supply ATA
         let instruction1 = anchor_spl::token::Transfer {
@@ -72,168 +469,271 @@ pub mod sepawithdraw {
             CpiContext::new(token_program.to_account_info(), instruction1),
             reserve_supply,
         )?;
-        totalamount -= reserve_supply;
-        msg!("Reserve supply transfer done...");
 
-        // Set presale round prices and balances
-        let r1_supply = (pub_supply.checked_mul(60).unwrap())
-            .checked_div(100)
-            .unwrap();
-        let r2_supply = (pub_supply.checked_mul(20).unwrap())
-            .checked_div(100)
-            .unwrap();
-        let r3_supply = (pub_supply.checked_mul(20).unwrap())
-            .checked_div(100)
-            .unwrap();
-
-        state.rounds[0].price = 0.002;
-        state.rounds[1].price = 0.003;
-        state.rounds[2].price = 0.004;
-        state.rounds[0].balance = r1_supply;
-        state.rounds[1].balance = r2_supply;
-        state.rounds[2].balance = r3_supply;
+        // Transfer burn supply to burn pool ATA
+        if burn_supply > 0 {
+            let instruction2 = anchor_spl::token::Transfer {
+                authority: admin.to_account_info(),
+                from: owner_ata.to_account_info(),
+                to: ctx.accounts.burn_pool_ata.to_account_info(),
+            };
+            token::transfer(
+                CpiContext::new(token_program.to_account_info(), instruction2),
+                burn_supply,
+            )?;
+        }
 
-        msg!("Total amount remaining: {}", totalamount);
+        emit!(TokensDistributed {
+            sale_id: state.sale_id,
+            total_supply: totalsupply,
+            public_supply: pub_supply,
+            reserve_supply,
+            burn_supply,
+        });
         Ok(())
     }
 
     pub fn start_round(ctx: Context<StartRound>, round: u8) -> Result<()> {
         let state = &mut ctx.accounts.state;
-        let clock = clock::Clock::get().unwrap();
+        let clock = clock::Clock::get()?;
 
         require!(
-            ctx.accounts.admin.key() == state.admin,
+            state.has_role(Role::Operator, &ctx.accounts.authority.key()),
             CustomError::Unauthorized
         );
+        require!(state.round_supply > 0, CustomError::NotDistributed);
+        require!(
+            state.status == SaleStatus::Active,
+            CustomError::SaleNotActive
+        );
 
         // Ensure the round number is valid
-        if round as usize >= state.rounds.len() {
+        if round >= state.round_count {
             return Err(CustomError::InvalidRound.into());
         }
 
+        // Ensure the round's configured window has not already passed
+        if clock.unix_timestamp > ctx.accounts.next_round.end_time {
+            return Err(CustomError::RoundExpired.into());
+        }
+
         // Ensure rounds are started in increasing order
         if state.current_active_phase != u8::MAX && round <= state.current_active_phase {
             return Err(CustomError::InvalidRoundOrder.into());
         }
 
         // Collect unsold tokens from the currently active round (if any)
+        let mut total_unsold_tokens = 0;
         if state.current_active_phase != u8::MAX {
-            let active_phase = state.current_active_phase as usize;
-            let active_round = &mut state.rounds[active_phase];
-
-            active_round.active = false;
-            active_round.end_time = clock.unix_timestamp;
-            let total_unsold_tokens = active_round.balance;
-            active_round.balance = 0;
-
-            // Add unsold tokens to the public pool
-            state.pub_supply = state
-                .pub_supply
-                .checked_add(total_unsold_tokens)
-                .unwrap();
+            let active_round = ctx
+                .accounts
+                .previous_round
+                .as_mut()
+                .ok_or(CustomError::InvalidRound)?;
+
+            total_unsold_tokens = active_round.end(state.sale_id, clock.unix_timestamp);
         }
 
-        // Start the new round
-        let new_start_time = clock.unix_timestamp;
-        let new_end_time = new_start_time + 1 * 24 * 60 * 60; // one-day duration
-        let new_round = &mut state.rounds[round as usize];
+        // Carve the round's share out of the public pool; the last round takes
+        // whatever remains so rounding dust is never stranded.
+        let new_round = &mut ctx.accounts.next_round;
+        let allocation = if round == state.round_count - 1 {
+            state
+                .round_supply
+                .checked_sub(state.round_supply_allocated)
+                .ok_or(CustomError::MathOverflow)?
+        } else {
+            bps_of(state.round_supply, new_round.allocation_bps)?
+        };
+        state.round_supply_allocated = state
+            .round_supply_allocated
+            .checked_add(allocation)
+            .ok_or(CustomError::MathOverflow)?;
+        state.pub_supply = state
+            .pub_supply
+            .checked_sub(allocation)
+            .ok_or(CustomError::MathOverflow)?;
+
+        // Start the new round within its configured window
         new_round.active = true;
-        new_round.start_time = new_start_time;
-        new_round.end_time = new_end_time;
+        new_round.balance = allocation;
+        state.release_unsold(total_unsold_tokens, Some(new_round))?;
+        let new_start_time = new_round.start_time;
+        let new_end_time = new_round.end_time;
 
         state.current_active_phase = round;
 
-        msg!(
-            "Round {} started. Start time: {}, End time: {}",
+        emit!(RoundStarted {
+            sale_id: state.sale_id,
             round,
-            new_start_time,
-            new_end_time
-        );
+            allocation,
+            start_time: new_start_time,
+            end_time: new_end_time,
+        });
         Ok(())
     }
 
-    pub fn purchasenow(ctx: Context<Purchasenow>, pay_amount: u64, round: u8) -> Result<()> {
-        let token_program = ctx.accounts.token_program.to_account_info();
-        let clock = clock::Clock::get().unwrap();
-        let buyer = &ctx.accounts.buyer.to_account_info();
+    pub fn purchasenow(
+        ctx: Context<Purchasenow>,
+        pay_amount: u64,
+        round: u8,
+        max_allocation: u64,
+        proof: Vec<[u8; 32]>,
+        min_tokens_out: u64,
+    ) -> Result<()> {
+        let clock = clock::Clock::get()?;
+        let buyer = ctx.accounts.buyer.key();
         let state = &mut ctx.accounts.state;
+        let current_round = &mut ctx.accounts.current_round;
+
+        let allowlisted = check_round_open(
+            state,
+            current_round,
+            &buyer,
+            max_allocation,
+            &proof,
+            clock.unix_timestamp,
+        )?;
 
-        // Ensure the round number is valid
-        if round as usize >= state.rounds.len() {
-            return Err(CustomError::InvalidRound.into());
-        }
-
-        // Ensure the round is active and within the time limit
-        let current_round = &mut state.rounds[round as usize];
-        if !current_round.active || clock.unix_timestamp > current_round.end_time {
-            return Err(CustomError::RoundExpired.into());
-        }
-
-        // Ensure the round has tokens available
-        if current_round.balance == 0 {
-            return Err(CustomError::InsufficientRoundBalance.into());
-        }
-
-        // Determine the price per token for the given round
-        let price_per_token = current_round.price;
-
-        // Calculate the number of tokens to transfer based on the price
-        let one_token_amount = 10u64.pow(ctx.accounts.mint.decimals as u32); // Token decimals
-        let one_payment_token_amount = 10u64.pow(ctx.accounts.payment_mint.decimals as u32); // Payment token decimals
-        let receivable_amount = ((pay_amount as f64 / price_per_token)
-            * (one_token_amount as f64 / one_payment_token_amount as f64))
-            as u64;
-
-        // Ensure there are enough tokens available in the round's balance
-        if receivable_amount > current_round.balance {
-            return Err(CustomError::InsufficientRoundBalance.into());
-        }
-
-        // Deduct tokens from the round's balance
-        current_round.balance = current_round
-            .balance
-            .checked_sub(receivable_amount)
-            .ok_or_else(|| CustomError::InsufficientRoundBalance)?;
-        current_round.tokens_sold += receivable_amount;
+        // Price the purchase in the buyer's chosen payment mint, converting the
+        // round's USD price at the oracle rate when the mint has a feed
+        let payment_mint_info = &mut ctx.accounts.payment_mint_info;
+        let quote = if payment_mint_info.price_feed == Pubkey::default() {
+            current_round.mint_prices[payment_mint_info.index as usize]
+        } else {
+            let price_feed = ctx
+                .accounts
+                .price_feed
+                .as_ref()
+                .ok_or(CustomError::InvalidPriceFeed)?;
+            require!(
+                price_feed.key() == payment_mint_info.price_feed,
+                CustomError::InvalidPriceFeed
+            );
+            require!(
+                current_round.usd_price.numerator > 0,
+                CustomError::PaymentMintNotPriced
+            );
+            read_price_feed(price_feed, payment_mint_info, clock.unix_timestamp)?
+                .quote(&current_round.usd_price, payment_mint_info.decimals)?
+        };
+        require!(quote.numerator > 0, CustomError::PaymentMintNotPriced);
+        let fill = quote_fill(
+            state,
+            current_round,
+            &quote,
+            pay_amount,
+            Currency::Spl(payment_mint_info.index),
+        )?;
+        record_purchase(
+            state,
+            current_round,
+            &mut ctx.accounts.vesting,
+            buyer,
+            &fill,
+            allowlisted.then_some(max_allocation),
+            min_tokens_out,
+            clock.unix_timestamp,
+        )?;
+        payment_mint_info.total_raised = payment_mint_info
+            .total_raised
+            .checked_add(fill.paid)
+            .ok_or(CustomError::MathOverflow)?;
 
-        // Transfer payment tokens from buyer to admin
+        // Escrow payment tokens from buyer in the sale's vault for that mint
         let cpi_accounts_payment = Transfer {
             from: ctx.accounts.buyer_payment_mint_ata.to_account_info(),
-            to: ctx.accounts.admin_ata.to_account_info(),
+            to: ctx.accounts.payment_vault.to_account_info(),
             authority: ctx.accounts.buyer.to_account_info(),
         };
         let cpi_program_payment = ctx.accounts.token_program.to_account_info();
         let cpi_ctx_payment = CpiContext::new(cpi_program_payment, cpi_accounts_payment);
-        token::transfer(cpi_ctx_payment, pay_amount)?;
+        token::transfer(cpi_ctx_payment, fill.paid)?;
 
-        // Create vesting account for buyer
-        let vesting = &mut ctx.accounts.vesting;
-        vesting.owner = ctx.accounts.buyer.key();
-        vesting.purchases.push(Purchase {
-            amount: receivable_amount,
-            start_time: clock.unix_timestamp,
-            round,
-            claimed: false,
-        });
+        Ok(())
+    }
 
-        msg!(
-            "Purchase completed. Buyer: {}. Amount: {}. Round: {}. Time: {}",
-            buyer.key(),
-            receivable_amount,
-            round,
-            clock.unix_timestamp
+    pub fn purchase_with_sol(
+        ctx: Context<PurchaseWithSol>,
+        lamports: u64,
+        round: u8,
+        max_allocation: u64,
+        proof: Vec<[u8; 32]>,
+        min_tokens_out: u64,
+    ) -> Result<()> {
+        let clock = clock::Clock::get()?;
+        let buyer = ctx.accounts.buyer.key();
+        let state = &mut ctx.accounts.state;
+        let current_round = &mut ctx.accounts.current_round;
+
+        let allowlisted = check_round_open(
+            state,
+            current_round,
+            &buyer,
+            max_allocation,
+            &proof,
+            clock.unix_timestamp,
+        )?;
+        require!(
+            current_round.sol_price.numerator > 0,
+            CustomError::SolPaymentsDisabled
         );
+
+        let fill = quote_fill(
+            state,
+            current_round,
+            &current_round.sol_price,
+            lamports,
+            Currency::Sol,
+        )?;
+        record_purchase(
+            state,
+            current_round,
+            &mut ctx.accounts.vesting,
+            buyer,
+            &fill,
+            allowlisted.then_some(max_allocation),
+            min_tokens_out,
+            clock.unix_timestamp,
+        )?;
+        state.total_raised_sol = state
+            .total_raised_sol
+            .checked_add(fill.paid)
+            .ok_or(CustomError::MathOverflow)?;
+
+        // Escrow lamports from buyer in the sale's SOL vault
+        system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: ctx.accounts.buyer.to_account_info(),
+                    to: ctx.accounts.sol_vault.to_account_info(),
+                },
+            ),
+            fill.paid,
+        )?;
+
         Ok(())
     }
 
     pub fn claim(ctx: Context<Claim>, round: u8, purchase_index: u64) -> Result<()> {
-        let clock = clock::Clock::get().unwrap();
+        let clock = clock::Clock::get()?;
         let current_time = clock.unix_timestamp;
         let claimant = &ctx.accounts.claimant;
         let vesting = &mut ctx.accounts.vesting;
 
-        // 5 minutes vesting period
-        let five_mins_in_seconds = 5 * 60;
+        require!(
+            ctx.accounts.state.status != SaleStatus::Refunding,
+            CustomError::SaleRefunding
+        );
+        ctx.accounts.state.check_not_paused(PAUSE_CLAIMS)?;
+
+        // Vesting runs from the sale-wide TGE
+        let tge_timestamp = ctx.accounts.state.tge_timestamp;
+        require!(
+            tge_timestamp > 0 && current_time >= tge_timestamp,
+            CustomError::TgeNotReached
+        );
 
         // Ensure the purchase index is valid
         if purchase_index as usize >= vesting.purchases.len() {
@@ -243,15 +743,22 @@ pub mod sepawithdraw {
         // Get a mutable reference to the purchase
         let purchase = vesting.purchases.get_mut(purchase_index as usize).ok_or(CustomError::InvalidPurchaseId)?;
 
+        // Ensure the purchase has not already been fully claimed
+        if purchase.claimed_amount >= purchase.amount {
+            return Err(CustomError::AlreadyClaimed.into());
+        }
+
+        // Pay out whatever has vested under the round's schedule but not been claimed yet
+        let claimable_amount = purchase.claim_vested(
+            &ctx.accounts.purchase_round.schedule,
+            tge_timestamp,
+            current_time,
+        )?;
         require!(
-            current_time >= purchase.start_time + five_mins_in_seconds,
+            purchase.claimed_amount > 0,
             CustomError::VestingPeriodNotEnded
         );
-
-        // Ensure the purchase has not already been claimed
-        if purchase.claimed {
-            return Err(CustomError::AlreadyClaimed.into());
-        }
+        require!(claimable_amount > 0, CustomError::NothingToClaim);
 
         let cpi_accounts = Transfer {
             from: ctx.accounts.pubsup_ata.to_account_info(),
@@ -265,167 +772,779 @@ pub mod sepawithdraw {
                 cpi_accounts,
                 &[&[
                     PUB_POOL_SEEDS,
-                    ctx.accounts.state.admin.as_ref(),
+                    ctx.accounts.state.key().as_ref(),
                     &[ctx.bumps["pubsup_pda"]],
                 ]],
             ),
-            purchase.amount,
+            claimable_amount,
         )?;
-        // Mark the purchase as claimed
-        purchase.claimed = true;
 
-        msg!(
-            "Claim successful. Claimant: {}. Amount: {}. Claimed: {}.",
-            claimant.key(),
-            purchase.amount,
-            purchase.claimed
-        );
+        let state = &mut ctx.accounts.state;
+        state.total_owed_to_buyers = state
+            .total_owed_to_buyers
+            .checked_sub(claimable_amount)
+            .ok_or(CustomError::MathOverflow)?;
+
+        emit!(TokensClaimed {
+            sale_id: ctx.accounts.state.sale_id,
+            claimant: claimant.key(),
+            round,
+            purchase_index,
+            amount: claimable_amount,
+            claimed_amount: purchase.claimed_amount,
+            total_amount: purchase.amount,
+        });
         Ok(())
     }
 
-    pub fn withdraw_remaining_tokens(ctx: Context<WithdrawRemainingTokens>) -> Result<()> {
-        let state = &mut ctx.accounts.state;
+    /// Claims every vested purchase across the claimant's vesting accounts,
+    /// passed as `(vesting, round)` pairs in the remaining accounts.
+    pub fn claim_all(ctx: Context<ClaimAll>) -> Result<()> {
+        let clock = clock::Clock::get()?;
+        let current_time = clock.unix_timestamp;
+        let claimant = ctx.accounts.claimant.key();
+        let state_key = ctx.accounts.state.key();
 
         require!(
-            ctx.accounts.admin.key() == state.admin,
-            CustomError::Unauthorized
+            ctx.accounts.state.status != SaleStatus::Refunding,
+            CustomError::SaleRefunding
         );
+        ctx.accounts.state.check_not_paused(PAUSE_CLAIMS)?;
 
-        // Deactivate the last active round if round 2 is active
-        if state.current_active_phase == 2 && state.rounds[2].active {
-            let last_round_balance = state.rounds[2].balance;
-            state.rounds[2].active = false;
-            state.rounds[2].end_time = clock::Clock::get().unwrap().unix_timestamp;
-
-            state.admin_remaining_tokens = state
-                .admin_remaining_tokens
-                .checked_add(last_round_balance)
-                .unwrap();
+        // Vesting runs from the sale-wide TGE
+        let tge_timestamp = ctx.accounts.state.tge_timestamp;
+        require!(
+            tge_timestamp > 0 && current_time >= tge_timestamp,
+            CustomError::TgeNotReached
+        );
 
-            state.rounds[2].balance = 0;
+        let pairs = ctx.remaining_accounts.chunks_exact(2);
+        require!(
+            pairs.len() > 0 && pairs.remainder().is_empty(),
+            CustomError::InvalidRemainingAccounts
+        );
 
-            msg!("Round 2 deactivated and unsold tokens transferred to admin_remaining_tokens");
+        let mut total_claimed: u64 = 0;
+        let mut purchases_claimed: u64 = 0;
+        for pair in pairs {
+            let mut vesting: Account<Vesting> = Account::try_from(&pair[0])?;
+            let round: Account<Round> = Account::try_from(&pair[1])?;
+
+            // Both accounts must be this sale's PDAs for the claimant
+            let (round_key, _) = Pubkey::find_program_address(
+                &[ROUND_SEED, state_key.as_ref(), &[round.index]],
+                ctx.program_id,
+            );
+            let (vesting_key, _) = Pubkey::find_program_address(
+                &[VEST_SEED, state_key.as_ref(), &[round.index], claimant.as_ref()],
+                ctx.program_id,
+            );
+            require!(
+                round.key() == round_key && vesting.key() == vesting_key,
+                CustomError::InvalidRemainingAccounts
+            );
+
+            for (purchase_index, purchase) in vesting.purchases.iter_mut().enumerate() {
+                let claimable_amount =
+                    purchase.claim_vested(&round.schedule, tge_timestamp, current_time)?;
+                if claimable_amount == 0 {
+                    continue;
+                }
+                total_claimed = total_claimed
+                    .checked_add(claimable_amount)
+                    .ok_or(CustomError::MathOverflow)?;
+                purchases_claimed += 1;
+
+                emit!(TokensClaimed {
+                    sale_id: ctx.accounts.state.sale_id,
+                    claimant,
+                    round: round.index,
+                    purchase_index: purchase_index as u64,
+                    amount: claimable_amount,
+                    claimed_amount: purchase.claimed_amount,
+                    total_amount: purchase.amount,
+                });
+            }
+
+            // Persist before the next pair so a repeated account cannot claim twice
+            vesting.exit(ctx.program_id)?;
         }
+        require!(total_claimed > 0, CustomError::NothingToClaim);
 
-        // Withdraw tokens from the public pool
-        let pubsup_balance = ctx.accounts.pubsup_ata.amount;
-        if pubsup_balance > 0 {
-            let transfer_instruction = Transfer {
-                from: ctx.accounts.pubsup_ata.to_account_info(),
-                to: ctx.accounts.admin_ata.to_account_info(),
-                authority: ctx.accounts.pubsup_pda.to_account_info(),
-            };
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.pubsup_ata.to_account_info(),
+            to: ctx.accounts.claimant_ata.to_account_info(),
+            authority: ctx.accounts.pubsup_pda.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        token::transfer(
+            CpiContext::new_with_signer(
+                cpi_program,
+                cpi_accounts,
+                &[&[
+                    PUB_POOL_SEEDS,
+                    state_key.as_ref(),
+                    &[ctx.bumps["pubsup_pda"]],
+                ]],
+            ),
+            total_claimed,
+        )?;
 
-            let pub_seeds = &[PUB_POOL_SEEDS.as_ref(), state.admin.as_ref(), &[ctx.bumps["pubsup_pda"]]];
-            let pub_signer = &[&pub_seeds[..]];
+        let state = &mut ctx.accounts.state;
+        state.total_owed_to_buyers = state
+            .total_owed_to_buyers
+            .checked_sub(total_claimed)
+            .ok_or(CustomError::MathOverflow)?;
+
+        emit!(AllClaimed {
+            sale_id: state.sale_id,
+            claimant,
+            total: total_claimed,
+            purchases: purchases_claimed,
+        });
+        Ok(())
+    }
+
+    pub fn refund(ctx: Context<Refund>, round: u8) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+        let vesting = &mut ctx.accounts.vesting;
+
+        require!(
+            state.status == SaleStatus::Refunding,
+            CustomError::SaleNotRefunding
+        );
+        state.check_not_paused(PAUSE_REFUNDS)?;
+
+        // Burn every untouched purchase paid in this mint or SOL and total up its
+        // escrowed payment
+        let refund_index = ctx.accounts.payment_mint_info.index;
+        let refundable = |p: &Purchase| {
+            p.claimed_amount == 0
+                && (p.currency == Currency::Spl(refund_index) || p.currency == Currency::Sol)
+        };
+        let mut refund_amount: u64 = 0;
+        let mut refund_lamports: u64 = 0;
+        let mut refund_value: u64 = 0;
+        let mut returned_tokens: u64 = 0;
+        for purchase in vesting.purchases.iter().filter(|p| refundable(p)) {
+            let refund_total = match purchase.currency {
+                Currency::Spl(_) => &mut refund_amount,
+                Currency::Sol => &mut refund_lamports,
+            };
+            *refund_total = refund_total
+                .checked_add(purchase.paid)
+                .ok_or(CustomError::MathOverflow)?;
+            refund_value = refund_value
+                .checked_add(purchase.value)
+                .ok_or(CustomError::MathOverflow)?;
+            returned_tokens = returned_tokens
+                .checked_add(purchase.amount)
+                .ok_or(CustomError::MathOverflow)?;
+        }
+        require!(returned_tokens > 0, CustomError::NothingToRefund);
+        vesting.purchases.retain(|p| !refundable(p));
+        vesting.total_purchased = vesting
+            .total_purchased
+            .checked_sub(returned_tokens)
+            .ok_or(CustomError::MathOverflow)?;
+        vesting.total_contributed = vesting
+            .total_contributed
+            .checked_sub(refund_value)
+            .ok_or(CustomError::MathOverflow)?;
+
+        // Refunded tokens are no longer owed and return to the public pool
+        state.pub_supply = state
+            .pub_supply
+            .checked_add(returned_tokens)
+            .ok_or(CustomError::MathOverflow)?;
+        state.total_owed_to_buyers = state
+            .total_owed_to_buyers
+            .checked_sub(returned_tokens)
+            .ok_or(CustomError::MathOverflow)?;
+        let purchase_round = &mut ctx.accounts.purchase_round;
+        purchase_round.tokens_sold = purchase_round
+            .tokens_sold
+            .checked_sub(returned_tokens)
+            .ok_or(CustomError::MathOverflow)?;
+        state.total_refunded = state
+            .total_refunded
+            .checked_add(refund_value)
+            .ok_or(CustomError::MathOverflow)?;
+        let payment_mint_info = &mut ctx.accounts.payment_mint_info;
+        payment_mint_info.total_refunded = payment_mint_info
+            .total_refunded
+            .checked_add(refund_amount)
+            .ok_or(CustomError::MathOverflow)?;
+
+        if refund_amount > 0 {
+            let transfer_instruction = Transfer {
+                from: ctx.accounts.payment_vault.to_account_info(),
+                to: ctx.accounts.buyer_payment_mint_ata.to_account_info(),
+                authority: ctx.accounts.vault_pda.to_account_info(),
+            };
+
+            let state_key = state.key();
+            let vault_seeds = &[VAULT_SEEDS.as_ref(), state_key.as_ref(), &[ctx.bumps["vault_pda"]]];
+            let vault_signer = &[&vault_seeds[..]];
 
             token::transfer(
-                CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), transfer_instruction, pub_signer),
-                pubsup_balance,
+                CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), transfer_instruction, vault_signer),
+                refund_amount,
             )?;
+        }
+        if refund_lamports > 0 {
+            move_lamports(
+                &ctx.accounts.sol_vault.to_account_info(),
+                &ctx.accounts.buyer.to_account_info(),
+                refund_lamports,
+            )?;
+        }
+
+        emit!(Refunded {
+            sale_id: state.sale_id,
+            buyer: ctx.accounts.buyer.key(),
+            round,
+            payment_mint: ctx.accounts.payment_mint_info.mint,
+            amount: refund_amount,
+            lamports: refund_lamports,
+            value: refund_value,
+            tokens_returned: returned_tokens,
+        });
+        Ok(())
+    }
+
+    pub fn finalize_sale(ctx: Context<FinalizeSale>) -> Result<()> {
+        let clock = clock::Clock::get()?;
+        let state = &mut ctx.accounts.state;
+        let last_round = &ctx.accounts.last_round;
+
+        require!(
+            state.status == SaleStatus::Active,
+            CustomError::SaleNotActive
+        );
 
-            state.pub_supply = 0;
+        // Anyone may finalize once the last round has ended, even if later rounds
+        // were never started; the operator may close the last round early
+        if clock.unix_timestamp <= last_round.end_time {
+            require!(
+                state.has_role(Role::Operator, &ctx.accounts.authority.key()),
+                CustomError::Unauthorized
+            );
+            require!(
+                state.current_active_phase == last_round.index,
+                CustomError::InvalidRoundOrder
+            );
+        }
 
-            msg!("Public pool tokens withdrawn: {}", pubsup_balance);
+        if state.current_active_phase != u8::MAX {
+            let active_round = ctx
+                .accounts
+                .active_round
+                .as_mut()
+                .ok_or(CustomError::InvalidRound)?;
+
+            if active_round.active {
+                let total_unsold_tokens = active_round.end(state.sale_id, clock.unix_timestamp);
+                state.release_unsold(total_unsold_tokens, None)?;
+            }
         }
+        state.close_sale();
+
+        Ok(())
+    }
+
+    pub fn set_tge(ctx: Context<SetTge>, tge_timestamp: i64) -> Result<()> {
+        let state = &mut ctx.accounts.state;
 
-        // Withdraw tokens from the reserve pool
-        let reserve_balance = ctx.accounts.reserve_ata.amount;
-        if reserve_balance > 0 {
+        require!(
+            state.has_role(Role::Operator, &ctx.accounts.authority.key()),
+            CustomError::Unauthorized
+        );
+        require!(state.tge_timestamp == 0, CustomError::TgeAlreadySet);
+        require!(
+            state.status == SaleStatus::Finalized,
+            CustomError::SaleNotFinalized
+        );
+        require!(tge_timestamp > 0, CustomError::InvalidTgeTimestamp);
+
+        state.tge_timestamp = tge_timestamp;
+
+        emit!(TgeSet {
+            sale_id: state.sale_id,
+            tge_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn withdraw_proceeds(ctx: Context<WithdrawProceeds>) -> Result<()> {
+        let state = &ctx.accounts.state;
+
+        require!(
+            state.has_role(Role::Treasurer, &ctx.accounts.authority.key()),
+            CustomError::Unauthorized
+        );
+        require!(
+            state.status == SaleStatus::Finalized,
+            CustomError::SaleNotFinalized
+        );
+
+        let proceeds = ctx.accounts.payment_vault.amount;
+        if proceeds > 0 {
             let transfer_instruction = Transfer {
-                from: ctx.accounts.reserve_ata.to_account_info(),
-                to: ctx.accounts.admin_ata.to_account_info(),
-                authority: ctx.accounts.reserve_pda.to_account_info(),
+                from: ctx.accounts.payment_vault.to_account_info(),
+                to: ctx.accounts.authority_payment_ata.to_account_info(),
+                authority: ctx.accounts.vault_pda.to_account_info(),
             };
 
-            let reserve_seeds = &[RES_POOL_SEEDS.as_ref(), state.admin.as_ref(), &[ctx.bumps["reserve_pda"]]];
-            let reserve_signer = &[&reserve_seeds[..]];
+            let state_key = state.key();
+            let vault_seeds = &[VAULT_SEEDS.as_ref(), state_key.as_ref(), &[ctx.bumps["vault_pda"]]];
+            let vault_signer = &[&vault_seeds[..]];
 
             token::transfer(
-                CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), transfer_instruction, reserve_signer),
-                reserve_balance,
+                CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), transfer_instruction, vault_signer),
+                proceeds,
             )?;
 
-            state.reserve_supply = 0;
+            let payment_mint_info = &mut ctx.accounts.payment_mint_info;
+            payment_mint_info.total_withdrawn = payment_mint_info
+                .total_withdrawn
+                .checked_add(proceeds)
+                .ok_or(CustomError::MathOverflow)?;
+
+            emit!(ProceedsWithdrawn {
+                sale_id: state.sale_id,
+                authority: ctx.accounts.authority.key(),
+                currency: Currency::Spl(payment_mint_info.index),
+                amount: proceeds,
+            });
+        } else {
+            msg!("No proceeds available in the payment vault to withdraw.");
+        }
 
-            msg!("Reserve pool tokens withdrawn: {}", reserve_balance);
+        // Sweep SOL proceeds, leaving the vault rent-exempt
+        let sol_vault = ctx.accounts.sol_vault.to_account_info();
+        let rent_exempt_lamports = Rent::get()?.minimum_balance(sol_vault.data_len());
+        let sol_proceeds = sol_vault.lamports().saturating_sub(rent_exempt_lamports);
+        if sol_proceeds > 0 {
+            move_lamports(&sol_vault, &ctx.accounts.authority.to_account_info(), sol_proceeds)?;
+            emit!(ProceedsWithdrawn {
+                sale_id: state.sale_id,
+                authority: ctx.accounts.authority.key(),
+                currency: Currency::Sol,
+                amount: sol_proceeds,
+            });
         }
 
         Ok(())
     }
 
-    pub fn withdraw_public_pool_tokens(ctx: Context<WithdrawPublicPoolTokens>) -> Result<()> {
+    /// Carves a vesting allocation for `beneficiary` out of the unallocated
+    /// reserve supply.
+    pub fn create_allocation(
+        ctx: Context<CreateAllocation>,
+        kind: AllocationKind,
+        beneficiary: Pubkey,
+        amount: u64,
+        schedule: VestingSchedule,
+    ) -> Result<()> {
         let state = &mut ctx.accounts.state;
 
         require!(
             ctx.accounts.admin.key() == state.admin,
             CustomError::Unauthorized
         );
-    
-        // Withdraw tokens from the public pool
-        let pubsup_balance = ctx.accounts.pubsup_ata.amount;
-        if pubsup_balance > 0 {
-            let transfer_instruction = Transfer {
+        require!(amount > 0, CustomError::InvalidAmount);
+        schedule.validate()?;
+
+        state.reserve_supply = state
+            .reserve_supply
+            .checked_sub(amount)
+            .ok_or(CustomError::InsufficientReserve)?;
+        state.reserve_owed = state
+            .reserve_owed
+            .checked_add(amount)
+            .ok_or(CustomError::MathOverflow)?;
+
+        let allocation = &mut ctx.accounts.allocation;
+        allocation.kind = kind;
+        allocation.beneficiary = beneficiary;
+        allocation.amount = amount;
+        allocation.claimed_amount = 0;
+        allocation.schedule = schedule;
+
+        emit!(AllocationCreated {
+            sale_id: state.sale_id,
+            kind,
+            beneficiary,
+            amount,
+        });
+        Ok(())
+    }
+
+    pub fn claim_allocation(ctx: Context<ClaimAllocation>, kind: AllocationKind) -> Result<()> {
+        let clock = clock::Clock::get()?;
+        let state_key = ctx.accounts.state.key();
+        let state = &mut ctx.accounts.state;
+        let allocation = &mut ctx.accounts.allocation;
+
+        state.check_not_paused(PAUSE_CLAIMS)?;
+
+        // Allocations vest from the sale-wide TGE like purchases do
+        require!(
+            state.tge_timestamp > 0 && clock.unix_timestamp >= state.tge_timestamp,
+            CustomError::TgeNotReached
+        );
+        let vested_amount = allocation.schedule.vested_amount(
+            allocation.amount,
+            state.tge_timestamp,
+            clock.unix_timestamp,
+        )?;
+        let claimable_amount = vested_amount.saturating_sub(allocation.claimed_amount);
+        require!(claimable_amount > 0, CustomError::NothingToClaim);
+        allocation.claimed_amount = vested_amount;
+
+        state.reserve_owed = state
+            .reserve_owed
+            .checked_sub(claimable_amount)
+            .ok_or(CustomError::MathOverflow)?;
+
+        let transfer_instruction = Transfer {
+            from: ctx.accounts.reserve_ata.to_account_info(),
+            to: ctx.accounts.beneficiary_ata.to_account_info(),
+            authority: ctx.accounts.reserve_pda.to_account_info(),
+        };
+        let reserve_seeds = &[RES_POOL_SEEDS.as_ref(), state_key.as_ref(), &[ctx.bumps["reserve_pda"]]];
+        let reserve_signer = &[&reserve_seeds[..]];
+        token::transfer(
+            CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), transfer_instruction, reserve_signer),
+            claimable_amount,
+        )?;
+
+        emit!(AllocationClaimed {
+            sale_id: state.sale_id,
+            kind,
+            beneficiary: allocation.beneficiary,
+            amount: claimable_amount,
+            claimed_amount: allocation.claimed_amount,
+            total_amount: allocation.amount,
+        });
+        Ok(())
+    }
+
+    /// Returns the unvested part of an allocation to the reserve supply, e.g.
+    /// when the sale refunds and TGE will never be set. Tokens vested so far
+    /// stay claimable by the beneficiary.
+    pub fn cancel_allocation(
+        ctx: Context<CancelAllocation>,
+        kind: AllocationKind,
+        beneficiary: Pubkey,
+    ) -> Result<()> {
+        let clock = clock::Clock::get()?;
+        let state = &mut ctx.accounts.state;
+        let allocation = &mut ctx.accounts.allocation;
+
+        require!(
+            ctx.accounts.admin.key() == state.admin,
+            CustomError::Unauthorized
+        );
+
+        let vested_amount = if state.tge_timestamp > 0 {
+            allocation.schedule.vested_amount(
+                allocation.amount,
+                state.tge_timestamp,
+                clock.unix_timestamp,
+            )?
+        } else {
+            0
+        };
+        let unvested_amount = allocation.amount.saturating_sub(vested_amount);
+        require!(unvested_amount > 0, CustomError::AllocationFullyVested);
+
+        // The remainder is fully vested, so the default schedule releases it at TGE
+        allocation.amount = vested_amount;
+        allocation.schedule = VestingSchedule::default();
+
+        state.reserve_owed = state
+            .reserve_owed
+            .checked_sub(unvested_amount)
+            .ok_or(CustomError::MathOverflow)?;
+        state.reserve_supply = state
+            .reserve_supply
+            .checked_add(unvested_amount)
+            .ok_or(CustomError::MathOverflow)?;
+
+        emit!(AllocationCancelled {
+            sale_id: state.sale_id,
+            kind,
+            beneficiary,
+            amount: unvested_amount,
+        });
+        Ok(())
+    }
+
+    /// Burns the burn pool, round tokens set aside by the unsold policy and,
+    /// once the sale has closed, the public pool's unsold tokens.
+    pub fn burn_unsold(ctx: Context<BurnUnsold>) -> Result<()> {
+        let state_key = ctx.accounts.state.key();
+        let state = &mut ctx.accounts.state;
+
+        require!(
+            state.has_role(Role::Operator, &ctx.accounts.authority.key()),
+            CustomError::Unauthorized
+        );
+
+        // Unsold public tokens may still be sold or refunded into while the sale
+        // runs; tokens the unsold policy marked for burning can go at any time
+        let mut unsold = std::mem::take(&mut state.unsold_to_burn);
+        if state.status != SaleStatus::Active {
+            unsold = unsold
+                .checked_add(std::mem::take(&mut state.pub_supply))
+                .ok_or(CustomError::MathOverflow)?;
+        }
+        let burn_pool = ctx.accounts.burn_pool_ata.amount;
+        require!(unsold > 0 || burn_pool > 0, CustomError::NothingToBurn);
+
+        if unsold > 0 {
+            let burn_instruction = Burn {
+                mint: ctx.accounts.mint.to_account_info(),
                 from: ctx.accounts.pubsup_ata.to_account_info(),
-                to: ctx.accounts.admin_ata.to_account_info(),
                 authority: ctx.accounts.pubsup_pda.to_account_info(),
             };
-    
-            let pub_seeds = &[PUB_POOL_SEEDS.as_ref(), state.admin.as_ref(), &[ctx.bumps["pubsup_pda"]]];
+            let pub_seeds = &[PUB_POOL_SEEDS.as_ref(), state_key.as_ref(), &[ctx.bumps["pubsup_pda"]]];
             let pub_signer = &[&pub_seeds[..]];
-    
-            token::transfer(
-                CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), transfer_instruction, pub_signer),
-                pubsup_balance,
+            token::burn(
+                CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), burn_instruction, pub_signer),
+                unsold,
             )?;
-    
-            state.pub_supply = 0;
-    
-            msg!("Public pool tokens withdrawn: {}", pubsup_balance);
-        } else {
-            msg!("No tokens available in the public pool to withdraw.");
         }
-    
+        if burn_pool > 0 {
+            let burn_instruction = Burn {
+                mint: ctx.accounts.mint.to_account_info(),
+                from: ctx.accounts.burn_pool_ata.to_account_info(),
+                authority: ctx.accounts.burn_pda.to_account_info(),
+            };
+            let burn_seeds = &[BUR_POOL_SEEDS.as_ref(), state_key.as_ref(), &[ctx.bumps["burn_pda"]]];
+            let burn_signer = &[&burn_seeds[..]];
+            token::burn(
+                CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), burn_instruction, burn_signer),
+                burn_pool,
+            )?;
+            state.burn_supply = 0;
+        }
+
+        state.burned_supply = state
+            .burned_supply
+            .checked_add(unsold)
+            .and_then(|v| v.checked_add(burn_pool))
+            .ok_or(CustomError::MathOverflow)?;
+
+        emit!(TokensBurned {
+            sale_id: state.sale_id,
+            unsold,
+            burn_pool,
+            burned_supply: state.burned_supply,
+        });
+        Ok(())
+    }
+
+    /// Moves unsold round tokens the unsold policy returned to the reserve from
+    /// the public pool into the reserve pool.
+    pub fn sweep_unsold_to_reserve(ctx: Context<SweepUnsoldToReserve>) -> Result<()> {
+        let state_key = ctx.accounts.state.key();
+        let state = &mut ctx.accounts.state;
+
+        require!(
+            state.has_role(Role::Operator, &ctx.accounts.authority.key()),
+            CustomError::Unauthorized
+        );
+
+        let amount = std::mem::take(&mut state.unsold_to_reserve);
+        require!(amount > 0, CustomError::InvalidAmount);
+
+        let transfer_instruction = Transfer {
+            from: ctx.accounts.pubsup_ata.to_account_info(),
+            to: ctx.accounts.reserve_ata.to_account_info(),
+            authority: ctx.accounts.pubsup_pda.to_account_info(),
+        };
+        let pub_seeds = &[PUB_POOL_SEEDS.as_ref(), state_key.as_ref(), &[ctx.bumps["pubsup_pda"]]];
+        let pub_signer = &[&pub_seeds[..]];
+        token::transfer(
+            CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), transfer_instruction, pub_signer),
+            amount,
+        )?;
+
+        state.reserve_supply = state
+            .reserve_supply
+            .checked_add(amount)
+            .ok_or(CustomError::MathOverflow)?;
+
+        emit!(UnsoldSweptToReserve {
+            sale_id: state.sale_id,
+            amount,
+            reserve_supply: state.reserve_supply,
+        });
+        Ok(())
+    }
+
+    pub fn set_unsold_policy(ctx: Context<SetUnsoldPolicy>, unsold_policy: UnsoldPolicy) -> Result<()> {
+        let state = &mut ctx.accounts.state;
+
+        require!(
+            state.has_role(Role::Operator, &ctx.accounts.authority.key()),
+            CustomError::Unauthorized
+        );
+        require!(
+            state.status == SaleStatus::Active,
+            CustomError::SaleNotActive
+        );
+
+        state.unsold_policy = unsold_policy;
+
+        emit!(UnsoldPolicySet {
+            sale_id: state.sale_id,
+            unsold_policy,
+        });
         Ok(())
     }
-    
-    pub fn withdraw_reserve_pool_tokens(ctx: Context<WithdrawReservePoolTokens>) -> Result<()> {
+
+    pub fn set_withdrawal_delay(ctx: Context<SetWithdrawalDelay>, withdrawal_delay: i64) -> Result<()> {
         let state = &mut ctx.accounts.state;
 
         require!(
             ctx.accounts.admin.key() == state.admin,
             CustomError::Unauthorized
         );
-    
-        // Withdraw tokens from the reserve pool
-        let reserve_balance = ctx.accounts.reserve_ata.amount;
-        if reserve_balance > 0 {
-            let transfer_instruction = Transfer {
-                from: ctx.accounts.reserve_ata.to_account_info(),
-                to: ctx.accounts.admin_ata.to_account_info(),
-                authority: ctx.accounts.reserve_pda.to_account_info(),
-            };
-    
-            let reserve_seeds = &[RES_POOL_SEEDS.as_ref(), state.admin.as_ref(), &[ctx.bumps["reserve_pda"]]];
-            let reserve_signer = &[&reserve_seeds[..]];
-    
-            token::transfer(
-                CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), transfer_instruction, reserve_signer),
-                reserve_balance,
-            )?;
-    
-            state.reserve_supply = 0;
-    
-            msg!("Reserve pool tokens withdrawn: {}", reserve_balance);
-        } else {
-            msg!("No tokens available in the reserve pool to withdraw.");
-        }
-    
+        // The delay may only grow, so it can't be shortened to rush a queued withdrawal
+        require!(
+            withdrawal_delay >= state.withdrawal_delay,
+            CustomError::InvalidWithdrawalDelay
+        );
+
+        state.withdrawal_delay = withdrawal_delay;
+
+        emit!(WithdrawalDelaySet {
+            sale_id: state.sale_id,
+            withdrawal_delay,
+        });
+        Ok(())
+    }
+
+    /// Queues a withdrawal from `pool` to `recipient`, executable once the
+    /// sale's withdrawal delay has passed. One withdrawal per pool may be pending.
+    pub fn queue_withdrawal(ctx: Context<QueueWithdrawal>, pool: Pool, amount: u64) -> Result<()> {
+        let clock = clock::Clock::get()?;
+        let state = &ctx.accounts.state;
+
+        require!(
+            state.has_role(Role::Treasurer, &ctx.accounts.authority.key()),
+            CustomError::Unauthorized
+        );
+        require!(amount > 0, CustomError::InvalidAmount);
+
+        let pending_withdrawal = &mut ctx.accounts.pending_withdrawal;
+        pending_withdrawal.pool = pool;
+        pending_withdrawal.amount = amount;
+        pending_withdrawal.recipient = ctx.accounts.recipient.key();
+        pending_withdrawal.queued_by = ctx.accounts.authority.key();
+        pending_withdrawal.queued_at = clock.unix_timestamp;
+        pending_withdrawal.executable_at = clock
+            .unix_timestamp
+            .checked_add(state.withdrawal_delay)
+            .ok_or(CustomError::MathOverflow)?;
+
+        emit!(WithdrawalQueued {
+            sale_id: state.sale_id,
+            pool,
+            amount,
+            recipient: pending_withdrawal.recipient,
+            queued_by: pending_withdrawal.queued_by,
+            executable_at: pending_withdrawal.executable_at,
+        });
+        Ok(())
+    }
+
+    pub fn cancel_withdrawal(ctx: Context<CancelWithdrawal>, pool: Pool) -> Result<()> {
+        let state = &ctx.accounts.state;
+
+        require!(
+            state.has_role(Role::Treasurer, &ctx.accounts.authority.key()),
+            CustomError::Unauthorized
+        );
+
+        emit!(WithdrawalCancelled {
+            sale_id: state.sale_id,
+            pool,
+            amount: ctx.accounts.pending_withdrawal.amount,
+            cancelled_by: ctx.accounts.authority.key(),
+        });
+        Ok(())
+    }
+
+    pub fn execute_withdrawal(ctx: Context<ExecuteWithdrawal>, pool: Pool) -> Result<()> {
+        let clock = clock::Clock::get()?;
+        let state_key = ctx.accounts.state.key();
+        let state = &mut ctx.accounts.state;
+        let pending_withdrawal = &ctx.accounts.pending_withdrawal;
+
+        require!(
+            state.has_role(Role::Treasurer, &ctx.accounts.authority.key()),
+            CustomError::Unauthorized
+        );
+        require!(
+            clock.unix_timestamp >= pending_withdrawal.executable_at,
+            CustomError::WithdrawalLocked
+        );
+        // Rounds sell out of the public pool until the sale closes
+        require!(
+            pool != Pool::Public || state.status != SaleStatus::Active,
+            CustomError::SaleNotClosed
+        );
+
+        // Tokens bought or allocated but not yet claimed stay in their pool, as
+        // do unsold tokens waiting to be burned or swept to the reserve
+        let liability = match pool {
+            Pool::Public => state
+                .total_owed_to_buyers
+                .saturating_add(state.unsold_to_burn)
+                .saturating_add(state.unsold_to_reserve),
+            Pool::Reserve => state.reserve_owed,
+        };
+        let available = ctx.accounts.pool_ata.amount.saturating_sub(liability);
+        let amount = pending_withdrawal.amount.min(available);
+        require!(amount > 0, CustomError::InsufficientFunds);
+
+        let transfer_instruction = Transfer {
+            from: ctx.accounts.pool_ata.to_account_info(),
+            to: ctx.accounts.recipient.to_account_info(),
+            authority: ctx.accounts.pool_pda.to_account_info(),
+        };
+        let pool_seeds = &[pool.seed(), state_key.as_ref(), &[ctx.bumps["pool_pda"]]];
+        let pool_signer = &[&pool_seeds[..]];
+        token::transfer(
+            CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), transfer_instruction, pool_signer),
+            amount,
+        )?;
+
+        let pool_supply = match pool {
+            Pool::Public => &mut state.pub_supply,
+            Pool::Reserve => &mut state.reserve_supply,
+        };
+        *pool_supply = pool_supply
+            .checked_sub(amount)
+            .ok_or(CustomError::InsufficientFunds)?;
+
+        emit!(PoolTokensWithdrawn {
+            sale_id: state.sale_id,
+            authority: ctx.accounts.authority.key(),
+            pool,
+            amount,
+        });
         Ok(())
     }
 }
 
 #[derive(Accounts)]
+#[instruction(sale_id: u64)]
 pub struct Initialize<'info> {
     ///CHECK:
     #[account(mut)]
@@ -437,7 +1556,7 @@ pub struct Initialize<'info> {
     #[account(
         init,
         payer = admin,
-        seeds = [b"state"],
+        seeds = [STATE_SEED.as_ref(), sale_id.to_le_bytes().as_ref()],
         bump,
         space = 8 + std::mem::size_of::<State>()
     )]
@@ -446,7 +1565,7 @@ pub struct Initialize<'info> {
     #[account(
         init,
         payer = admin,
-        seeds = [PUB_POOL_SEEDS.as_ref(), admin.key().as_ref()],
+        seeds = [PUB_POOL_SEEDS.as_ref(), state.key().as_ref()],
         bump,
         space = 8 + std::mem::size_of::<Balance>()
     )]
@@ -455,334 +1574,1596 @@ pub struct Initialize<'info> {
     #[account(
         init,
         payer = admin,
-        seeds = [RES_POOL_SEEDS.as_ref(), admin.key().as_ref()],
+        seeds = [RES_POOL_SEEDS.as_ref(), state.key().as_ref()],
         bump,
         space = 8 + std::mem::size_of::<Balance>()
     )]
     pub reserve_pda: Account<'info, Balance>,
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, token::Token>,
-}
-
-#[derive(Accounts)]
-pub struct Distoken<'info> {
-    ///CHECK:
-    #[account(mut)]
-    pub admin: Signer<'info>,
     #[account(
-        init_if_needed,
+        init,
         payer = admin,
-        associated_token::mint = mint,
-        associated_token::authority = admin,
-    )]
-    pub admin_ata: Box<Account<'info, TokenAccount>>,
-    #[account(mut)]
-    pub mint: Box<Account<'info, Mint>>,
-    #[account(
-        mut,
-        seeds = [b"state"],
+        seeds = [BUR_POOL_SEEDS.as_ref(), state.key().as_ref()],
         bump,
+        space = 8 + std::mem::size_of::<Balance>()
     )]
-    pub state: Box<Account<'info, State>>,
+    pub burn_pda: Box<Account<'info, Balance>>,
     ///CHECK:
     #[account(
-        mut,
-        seeds = [PUB_POOL_SEEDS.as_ref(), admin.key().as_ref()],
-        bump,
-    )]
-    pub pubsup_pda: Box<Account<'info, Balance>>,
-    #[account(
-        init_if_needed,
+        init,
         payer = admin,
-        associated_token::mint = mint,
-        associated_token::authority = pubsup_pda,
+        seeds = [VAULT_SEEDS.as_ref(), state.key().as_ref()],
+        bump,
+        space = 8 + std::mem::size_of::<Balance>()
     )]
-    pub pubsup_ata: Box<Account<'info, TokenAccount>>,
+    pub vault_pda: Account<'info, Balance>,
     ///CHECK:
     #[account(
-        mut,
-        seeds = [RES_POOL_SEEDS.as_ref(), admin.key().as_ref()],
+        init,
+        payer = admin,
+        seeds = [SOL_VAULT_SEEDS.as_ref(), state.key().as_ref()],
         bump,
+        space = 8 + std::mem::size_of::<Balance>()
     )]
-    pub reserve_pda: Account<'info, Balance>,
+    pub sol_vault: Account<'info, Balance>,
     #[account(
-        init_if_needed,
+        init,
         payer = admin,
-        associated_token::mint = mint,
-        associated_token::authority = reserve_pda,
+        seeds = [PAYMENT_MINT_SEED.as_ref(), state.key().as_ref(), payment_mint.key().as_ref()],
+        bump,
+        space = 8 + std::mem::size_of::<PaymentMint>()
     )]
-    pub reserve_pool_ata: Box<Account<'info, TokenAccount>>,
+    pub payment_mint_info: Box<Account<'info, PaymentMint>>,
     pub system_program: Program<'info, System>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
     pub token_program: Program<'info, token::Token>,
 }
 
-#[derive(Accounts)]
-#[instruction(pay_amount: u64, round: u8)]
-pub struct Purchasenow<'info> {
-    ///CHECK:
-    #[account(mut)]
-    pub admin: AccountInfo<'info>,
-    #[account(
-        init_if_needed,
-        payer = buyer,
-        associated_token::mint = payment_mint,
-        associated_token::authority = admin,
-    )]
-    pub admin_ata: Account<'info, TokenAccount>,
-    #[account(
-        mut,
-        seeds = [b"state"],
-        bump,
-    )]
-    pub state: Box<Account<'info, State>>,
-    #[account(mut)]
-    pub mint: Box<Account<'info, Mint>>,
-    #[account(mut)]
-    pub payment_mint: Box<Account<'info, Mint>>,
-    #[account(mut)]
-    pub buyer: Signer<'info>,
-    #[account(
-        init_if_needed,
-        payer = buyer,
-        associated_token::mint = payment_mint,
-        associated_token::authority = buyer,
-    )]
-    pub buyer_payment_mint_ata: Account<'info, TokenAccount>,
-    #[account(
-        init_if_needed,
-        payer = buyer,
-        space = 8 + Vesting::MAX_SIZE,
-        seeds = [VEST_SEED.as_ref(), [round].as_ref(), buyer.key().as_ref()],
-        bump
+/// Returns `bps` basis points of `amount`, rounded down.
+fn bps_of(amount: u64, bps: u16) -> Result<u64> {
+    let value = (amount as u128)
+        .checked_mul(bps as u128)
+        .ok_or(CustomError::MathOverflow)?
+        / BPS_DENOMINATOR as u128;
+    u64::try_from(value).map_err(|_| CustomError::MathOverflow.into())
+}
+
+/// A priced purchase: `tokens` bought for `paid` in `currency`, worth `value`
+/// in payment-mint base units.
+struct Fill {
+    tokens: u64,
+    paid: u64,
+    value: u64,
+    currency: Currency,
+}
+
+/// Prices an offer of `offered` base units at `quote`. Limits and caps are
+/// denominated in the sale's payment mint, so the fill is valued at the round's
+/// reference `price`, truncated to the remaining hard cap, and charged only
+/// what the whole tokens bought cost (rounded up).
+fn quote_fill(
+    state: &State,
+    round: &Round,
+    quote: &Price,
+    offered: u64,
+    currency: Currency,
+) -> Result<Fill> {
+    let mut tokens = quote.tokens_for(offered)?;
+
+    // The minimum applies to what the buyer offers, so a fill truncated by
+    // the hard cap still goes through
+    if round.price.cost_of(tokens)? < round.min_contribution {
+        return Err(CustomError::BelowMinContribution.into());
+    }
+
+    // Partially fill a purchase that would exceed the hard cap; the excess is
+    // never pulled from the buyer
+    if state.hard_cap > 0 {
+        let remaining_cap = state.hard_cap.saturating_sub(state.total_raised);
+        tokens = tokens.min(round.price.tokens_for(remaining_cap)?);
+    }
+
+    Ok(Fill {
+        tokens,
+        paid: quote.cost_of(tokens)?,
+        value: round.price.cost_of(tokens)?,
+        currency,
+    })
+}
+
+/// Ensures `round` is open for purchases and verifies the buyer's allowlist
+/// proof. Returns whether the round is allowlisted.
+fn check_round_open(
+    state: &State,
+    round: &Round,
+    buyer: &Pubkey,
+    max_allocation: u64,
+    proof: &[[u8; 32]],
+    now: i64,
+) -> Result<bool> {
+    require!(
+        state.status == SaleStatus::Active,
+        CustomError::SaleNotActive
+    );
+    state.check_not_paused(PAUSE_PURCHASES)?;
+
+    // Ensure the round is active and within the time limit
+    if !round.active || now > round.end_time {
+        return Err(CustomError::RoundExpired.into());
+    }
+    if now < round.start_time {
+        return Err(CustomError::RoundNotStarted.into());
+    }
+
+    // Ensure the round has tokens available
+    if round.balance == 0 {
+        return Err(CustomError::InsufficientRoundBalance.into());
+    }
+
+    // Allowlisted rounds require a proof of the buyer's (wallet, max allocation) leaf
+    let allowlisted = round.allowlist_root != [0u8; 32];
+    if allowlisted && !verify_allowlist_proof(&round.allowlist_root, buyer, max_allocation, proof) {
+        return Err(CustomError::NotAllowlisted.into());
+    }
+    Ok(allowlisted)
+}
+
+/// Applies the round and wallet limits to `fill`, then records it against the
+/// round, the sale totals and the buyer's vesting account.
+#[allow(clippy::too_many_arguments)]
+fn record_purchase(
+    state: &mut State,
+    round: &mut Round,
+    vesting: &mut Vesting,
+    buyer: Pubkey,
+    fill: &Fill,
+    allowlist_allocation: Option<u64>,
+    min_tokens_out: u64,
+    now: i64,
+) -> Result<()> {
+    if fill.tokens == 0 {
+        return Err(CustomError::PurchaseTooSmall.into());
+    }
+
+    // Protect the buyer against landing at a worse fill than they expected
+    if fill.tokens < min_tokens_out {
+        return Err(CustomError::SlippageExceeded.into());
+    }
+
+    // Ensure there are enough tokens available in the round's balance
+    if fill.tokens > round.balance {
+        return Err(CustomError::InsufficientRoundBalance.into());
+    }
+
+    // Enforce the round's per-wallet cap
+    let wallet_total = vesting
+        .total_purchased
+        .checked_add(fill.tokens)
+        .ok_or(CustomError::MathOverflow)?;
+    if round.wallet_cap > 0 && wallet_total > round.wallet_cap {
+        return Err(CustomError::WalletCapExceeded.into());
+    }
+    if matches!(allowlist_allocation, Some(allocation) if wallet_total > allocation) {
+        return Err(CustomError::AllocationExceeded.into());
+    }
+
+    // Enforce the round's per-wallet contribution limit
+    let wallet_contributed = vesting
+        .total_contributed
+        .checked_add(fill.value)
+        .ok_or(CustomError::MathOverflow)?;
+    if round.max_contribution > 0 && wallet_contributed > round.max_contribution {
+        return Err(CustomError::ExceedsMaxContribution.into());
+    }
+    if vesting.purchases.len() >= MAX_PURCHASES {
+        return Err(CustomError::TooManyPurchases.into());
+    }
+
+    // Deduct tokens from the round's balance
+    round.balance = round
+        .balance
+        .checked_sub(fill.tokens)
+        .ok_or(CustomError::InsufficientRoundBalance)?;
+    round.tokens_sold = round
+        .tokens_sold
+        .checked_add(fill.tokens)
+        .ok_or(CustomError::MathOverflow)?;
+    state.total_raised = state
+        .total_raised
+        .checked_add(fill.value)
+        .ok_or(CustomError::MathOverflow)?;
+    state.total_owed_to_buyers = state
+        .total_owed_to_buyers
+        .checked_add(fill.tokens)
+        .ok_or(CustomError::MathOverflow)?;
+
+    // Reaching the hard cap, or coming within less than one token of it, ends the sale
+    if state.hard_cap > 0
+        && round
+            .price
+            .tokens_for(state.hard_cap.saturating_sub(state.total_raised))?
+            == 0
+    {
+        let total_unsold_tokens = round.end(state.sale_id, now);
+        state.release_unsold(total_unsold_tokens, None)?;
+        state.close_sale();
+    }
+
+    emit!(TokensPurchased {
+        sale_id: state.sale_id,
+        buyer,
+        round: round.index,
+        purchase_index: vesting.purchases.len() as u64,
+        currency: fill.currency,
+        tokens: fill.tokens,
+        paid: fill.paid,
+        value: fill.value,
+        total_raised: state.total_raised,
+        timestamp: now,
+    });
+
+    // Create vesting account for buyer
+    vesting.owner = buyer;
+    vesting.total_purchased = wallet_total;
+    vesting.total_contributed = wallet_contributed;
+    vesting.purchases.push(Purchase {
+        amount: fill.tokens,
+        paid: fill.paid,
+        value: fill.value,
+        currency: fill.currency,
+        start_time: now,
+        round: round.index,
+        claimed_amount: 0,
+    });
+    Ok(())
+}
+
+/// Moves lamports out of an account owned by this program.
+fn move_lamports(from: &AccountInfo, to: &AccountInfo, amount: u64) -> Result<()> {
+    let from_lamports = from
+        .lamports()
+        .checked_sub(amount)
+        .ok_or(CustomError::InsufficientFunds)?;
+    let to_lamports = to
+        .lamports()
+        .checked_add(amount)
+        .ok_or(CustomError::MathOverflow)?;
+    **from.try_borrow_mut_lamports()? = from_lamports;
+    **to.try_borrow_mut_lamports()? = to_lamports;
+    Ok(())
+}
+
+/// Reads a Pyth price account, rejecting accounts the Pyth program doesn't own
+/// and prices that aren't trading, are older
+/// than the mint's `max_price_age` or whose confidence interval is wider than
+/// `max_confidence_bps` of the price.
+fn read_price_feed(
+    price_feed: &AccountInfo,
+    payment_mint_info: &PaymentMint,
+    now: i64,
+) -> Result<OraclePrice> {
+    require!(
+        pyth_program::check_id(price_feed.owner),
+        CustomError::InvalidPriceFeed
+    );
+    let data = price_feed.try_borrow_data()?;
+    require!(
+        data.len() >= PYTH_PRICE_ACCOUNT_LEN,
+        CustomError::InvalidPriceFeed
+    );
+    let read_u32 = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+    let read_u64 = |at: usize| u64::from_le_bytes(data[at..at + 8].try_into().unwrap());
+
+    require!(
+        read_u32(0) == PYTH_MAGIC && read_u32(8) == PYTH_PRICE_ACCOUNT_TYPE,
+        CustomError::InvalidPriceFeed
+    );
+    let expo = read_u32(20) as i32;
+    let timestamp = read_u64(96) as i64;
+    let price = read_u64(208) as i64;
+    let conf = read_u64(216);
+    let status = read_u32(224);
+
+    require!(
+        status == PYTH_STATUS_TRADING && price > 0,
+        CustomError::InvalidOraclePrice
+    );
+    require!(
+        now.saturating_sub(timestamp) <= payment_mint_info.max_price_age,
+        CustomError::StaleOraclePrice
+    );
+    require!(
+        (conf as u128) * (BPS_DENOMINATOR as u128)
+            <= (price as u128) * (payment_mint_info.max_confidence_bps as u128),
+        CustomError::OracleConfidenceTooWide
+    );
+
+    Ok(OraclePrice {
+        price: price as u64,
+        expo,
+    })
+}
+
+/// Checks `proof` for the leaf `keccak(wallet || max_allocation_le)` against
+/// `root`, hashing each pair of nodes in sorted order.
+fn verify_allowlist_proof(
+    root: &[u8; 32],
+    wallet: &Pubkey,
+    max_allocation: u64,
+    proof: &[[u8; 32]],
+) -> bool {
+    let mut node = keccak::hashv(&[wallet.as_ref(), &max_allocation.to_le_bytes()]).0;
+    for sibling in proof {
+        node = if node <= *sibling {
+            keccak::hashv(&[&node, sibling]).0
+        } else {
+            keccak::hashv(&[sibling, &node]).0
+        };
+    }
+    node == *root
+}
+
+#[derive(Accounts)]
+pub struct ProposeAdmin<'info> {
+    #[account(address = state.admin)]
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
     )]
-    pub vesting: Account<'info, Vesting>,
-    pub token_program: Program<'info, token::Token>,
-    pub system_program: Program<'info, System>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub state: Box<Account<'info, State>>,
 }
 
 #[derive(Accounts)]
-#[instruction(_round: u8, purchase_index: u64)]
-pub struct Claim<'info> {
-    ///CHECK:
+pub struct AcceptAdmin<'info> {
+    pub new_admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
+    )]
+    pub state: Box<Account<'info, State>>,
+}
+
+#[derive(Accounts)]
+pub struct SetRole<'info> {
+    #[account(address = state.admin)]
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
+    )]
+    pub state: Box<Account<'info, State>>,
+}
+
+#[derive(Accounts)]
+pub struct SetPaused<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
+    )]
+    pub state: Box<Account<'info, State>>,
+}
+
+#[derive(Accounts)]
+pub struct AddPaymentMint<'info> {
     #[account(mut)]
-    pub claimant: Signer<'info>,
+    pub authority: Signer<'info>,
     #[account(
         mut,
-        seeds = [b"state"],
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
+    )]
+    pub state: Box<Account<'info, State>>,
+    pub payment_mint: Box<Account<'info, Mint>>,
+    #[account(
+        init,
+        payer = authority,
+        seeds = [PAYMENT_MINT_SEED.as_ref(), state.key().as_ref(), payment_mint.key().as_ref()],
         bump,
+        space = 8 + std::mem::size_of::<PaymentMint>()
+    )]
+    pub payment_mint_info: Box<Account<'info, PaymentMint>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetPriceFeed<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
     )]
     pub state: Box<Account<'info, State>>,
-    ///CHECK:
     #[account(
         mut,
-        seeds = [PUB_POOL_SEEDS.as_ref(), state.admin.as_ref()],
+        seeds = [PAYMENT_MINT_SEED.as_ref(), state.key().as_ref(), payment_mint_info.mint.as_ref()],
         bump,
     )]
-    pub pubsup_pda: Box<Account<'info, Balance>>,
+    pub payment_mint_info: Box<Account<'info, PaymentMint>>,
+    ///CHECK: parsed as a Pyth price account
+    pub price_feed: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+#[instruction(round: u8)]
+pub struct SetMintPrice<'info> {
+    pub authority: Signer<'info>,
     #[account(
-        init_if_needed,
-        payer = claimant,
-        associated_token::mint = mint,
-        associated_token::authority = pubsup_pda,
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
     )]
-    pub pubsup_ata: Box<Account<'info, TokenAccount>>,
-    #[account(mut)]
-    pub mint: Box<Account<'info, Mint>>,
-    ///CHECK:
+    pub state: Box<Account<'info, State>>,
     #[account(
         mut,
-        seeds = [VEST_SEED.as_ref(), [_round].as_ref(), claimant.key().as_ref()],
-        bump
+        seeds = [ROUND_SEED.as_ref(), state.key().as_ref(), [round].as_ref()],
+        bump,
     )]
-    pub vesting: Box<Account<'info, Vesting>>,
+    pub round_account: Box<Account<'info, Round>>,
     #[account(
-        init_if_needed,
-        payer = claimant,
-        associated_token::mint = mint,
-        associated_token::authority = claimant,
+        seeds = [PAYMENT_MINT_SEED.as_ref(), state.key().as_ref(), payment_mint_info.mint.as_ref()],
+        bump,
     )]
-    pub claimant_ata: Box<Account<'info, TokenAccount>>,
-    pub token_program: Program<'info, token::Token>,
-    pub system_program: Program<'info, System>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub payment_mint_info: Box<Account<'info, PaymentMint>>,
 }
 
 #[derive(Accounts)]
-pub struct StartRound<'info> {
+pub struct AddRound<'info> {
     ///CHECK:
-    #[account(mut, address = state.admin)]
-    pub admin: Signer<'info>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
     #[account(
         mut,
-        seeds = [b"state"],
-        bump,
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
     )]
     pub state: Box<Account<'info, State>>,
+    /// The most recently added round, required once any round exists.
+    #[account(
+        seeds = [
+            ROUND_SEED.as_ref(),
+            state.key().as_ref(),
+            [state.round_count.saturating_sub(1)].as_ref()
+        ],
+        bump,
+    )]
+    pub previous_round: Option<Box<Account<'info, Round>>>,
+    #[account(
+        init,
+        payer = authority,
+        seeds = [ROUND_SEED.as_ref(), state.key().as_ref(), [state.round_count].as_ref()],
+        bump,
+        space = 8 + std::mem::size_of::<Round>()
+    )]
+    pub round: Box<Account<'info, Round>>,
     pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
 }
 
 #[derive(Accounts)]
-pub struct WithdrawRemainingTokens<'info> {
+#[instruction(round: u8)]
+pub struct SetAllowlistRoot<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
+    )]
+    pub state: Box<Account<'info, State>>,
+    #[account(
+        mut,
+        seeds = [ROUND_SEED.as_ref(), state.key().as_ref(), [round].as_ref()],
+        bump,
+    )]
+    pub round_account: Box<Account<'info, Round>>,
+}
+
+#[derive(Accounts)]
+pub struct Distoken<'info> {
     ///CHECK:
-    #[account(mut, address = state.admin)]
+    #[account(mut)]
     pub admin: Signer<'info>,
+    #[account(
+        init_if_needed,
+        payer = admin,
+        associated_token::mint = mint,
+        associated_token::authority = admin,
+    )]
+    pub admin_ata: Box<Account<'info, TokenAccount>>,
+    #[account(mut, address = state.mint)]
+    pub mint: Box<Account<'info, Mint>>,
     #[account(
         mut,
-        seeds = [b"state"],
-        bump,
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
     )]
     pub state: Box<Account<'info, State>>,
+    ///CHECK:
     #[account(
         mut,
-        seeds = [PUB_POOL_SEEDS.as_ref(), state.admin.as_ref()],
+        seeds = [PUB_POOL_SEEDS.as_ref(), state.key().as_ref()],
         bump,
     )]
     pub pubsup_pda: Box<Account<'info, Balance>>,
     #[account(
-        mut,
+        init_if_needed,
+        payer = admin,
         associated_token::mint = mint,
         associated_token::authority = pubsup_pda,
     )]
     pub pubsup_ata: Box<Account<'info, TokenAccount>>,
+    ///CHECK:
     #[account(
         mut,
-        seeds = [RES_POOL_SEEDS.as_ref(), state.admin.as_ref()],
+        seeds = [RES_POOL_SEEDS.as_ref(), state.key().as_ref()],
         bump,
     )]
-    pub reserve_pda: Box<Account<'info, Balance>>,
+    pub reserve_pda: Account<'info, Balance>,
     #[account(
-        mut,
+        init_if_needed,
+        payer = admin,
         associated_token::mint = mint,
         associated_token::authority = reserve_pda,
     )]
-    pub reserve_ata: Box<Account<'info, TokenAccount>>,
+    pub reserve_pool_ata: Box<Account<'info, TokenAccount>>,
     #[account(
-        mut,
+        seeds = [BUR_POOL_SEEDS.as_ref(), state.key().as_ref()],
+        bump,
+    )]
+    pub burn_pda: Box<Account<'info, Balance>>,
+    #[account(
+        init_if_needed,
+        payer = admin,
         associated_token::mint = mint,
-        associated_token::authority = admin,
+        associated_token::authority = burn_pda,
     )]
-    pub admin_ata: Box<Account<'info, TokenAccount>>,
-    #[account(mut)]
-    pub mint: Box<Account<'info, Mint>>,
-    pub token_program: Program<'info, token::Token>,
+    pub burn_pool_ata: Box<Account<'info, TokenAccount>>,
     pub system_program: Program<'info, System>,
     pub associated_token_program: Program<'info, AssociatedToken>,
+    pub token_program: Program<'info, token::Token>,
 }
 
 #[derive(Accounts)]
-pub struct WithdrawPublicPoolTokens<'info> {
-    ///CHECK:
-    #[account(mut, address = state.admin)]
-    pub admin: Signer<'info>,
+#[instruction(pay_amount: u64, round: u8)]
+pub struct Purchasenow<'info> {
     #[account(
         mut,
-        seeds = [b"state"],
-        bump,
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
     )]
     pub state: Box<Account<'info, State>>,
     #[account(
         mut,
-        seeds = [PUB_POOL_SEEDS.as_ref(), state.admin.as_ref()],
+        seeds = [ROUND_SEED.as_ref(), state.key().as_ref(), [round].as_ref()],
         bump,
     )]
-    pub pubsup_pda: Box<Account<'info, Balance>>,
+    pub current_round: Box<Account<'info, Round>>,
+    #[account(mut, address = state.mint)]
+    pub mint: Box<Account<'info, Mint>>,
+    pub payment_mint: Box<Account<'info, Mint>>,
     #[account(
         mut,
-        associated_token::mint = mint,
-        associated_token::authority = pubsup_pda,
+        seeds = [PAYMENT_MINT_SEED.as_ref(), state.key().as_ref(), payment_mint.key().as_ref()],
+        bump,
     )]
-    pub pubsup_ata: Box<Account<'info, TokenAccount>>,
+    pub payment_mint_info: Box<Account<'info, PaymentMint>>,
+    ///CHECK: checked against the payment mint's registered price feed
+    pub price_feed: Option<UncheckedAccount<'info>>,
+    #[account(mut)]
+    pub buyer: Signer<'info>,
     #[account(
-        mut,
-        associated_token::mint = mint,
-        associated_token::authority = admin,
+        init_if_needed,
+        payer = buyer,
+        associated_token::mint = payment_mint,
+        associated_token::authority = buyer,
     )]
-    pub admin_ata: Box<Account<'info, TokenAccount>>,
-    #[account(mut)]
-    pub mint: Box<Account<'info, Mint>>,
+    pub buyer_payment_mint_ata: Account<'info, TokenAccount>,
+    #[account(
+        seeds = [VAULT_SEEDS.as_ref(), state.key().as_ref()],
+        bump,
+    )]
+    pub vault_pda: Box<Account<'info, Balance>>,
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        associated_token::mint = payment_mint,
+        associated_token::authority = vault_pda,
+    )]
+    pub payment_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        space = 8 + Vesting::MAX_SIZE,
+        seeds = [VEST_SEED.as_ref(), state.key().as_ref(), [round].as_ref(), buyer.key().as_ref()],
+        bump
+    )]
+    pub vesting: Account<'info, Vesting>,
     pub token_program: Program<'info, token::Token>,
     pub system_program: Program<'info, System>,
     pub associated_token_program: Program<'info, AssociatedToken>,
 }
 
 #[derive(Accounts)]
-pub struct WithdrawReservePoolTokens<'info> {
-    ///CHECK:
-    #[account(mut, address = state.admin)]
-    pub admin: Signer<'info>,
+#[instruction(lamports: u64, round: u8)]
+pub struct PurchaseWithSol<'info> {
     #[account(
         mut,
-        seeds = [b"state"],
-        bump,
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
     )]
     pub state: Box<Account<'info, State>>,
     #[account(
         mut,
-        seeds = [RES_POOL_SEEDS.as_ref(), state.admin.as_ref()],
+        seeds = [ROUND_SEED.as_ref(), state.key().as_ref(), [round].as_ref()],
         bump,
     )]
-    pub reserve_pda: Box<Account<'info, Balance>>,
+    pub current_round: Box<Account<'info, Round>>,
+    #[account(mut)]
+    pub buyer: Signer<'info>,
     #[account(
-        mut,
-        associated_token::mint = mint,
-        associated_token::authority = reserve_pda,
+        init_if_needed,
+        payer = buyer,
+        space = 8 + Vesting::MAX_SIZE,
+        seeds = [VEST_SEED.as_ref(), state.key().as_ref(), [round].as_ref(), buyer.key().as_ref()],
+        bump
     )]
-    pub reserve_ata: Box<Account<'info, TokenAccount>>,
+    pub vesting: Account<'info, Vesting>,
     #[account(
         mut,
-        associated_token::mint = mint,
-        associated_token::authority = admin,
+        seeds = [SOL_VAULT_SEEDS.as_ref(), state.key().as_ref()],
+        bump,
     )]
-    pub admin_ata: Box<Account<'info, TokenAccount>>,
-    #[account(mut)]
-    pub mint: Box<Account<'info, Mint>>,
-    pub token_program: Program<'info, token::Token>,
+    pub sol_vault: Box<Account<'info, Balance>>,
     pub system_program: Program<'info, System>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-}
-
-
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
-pub struct Round {
-    pub active: bool,
-    pub price: f64,
-    pub balance: u64,
-    pub tokens_sold: u64,
-    pub start_time: i64,
-    pub end_time: i64,
 }
 
-#[account]
-pub struct State {
-    pub admin: Pubkey,
-    pub payment_mint: Pubkey,
-    pub mint: Pubkey,
-    pub pub_supply: u64,
+#[derive(Accounts)]
+#[instruction(_round: u8, purchase_index: u64)]
+pub struct Claim<'info> {
+    ///CHECK:
+    #[account(mut)]
+    pub claimant: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
+    )]
+    pub state: Box<Account<'info, State>>,
+    ///CHECK:
+    #[account(
+        mut,
+        seeds = [PUB_POOL_SEEDS.as_ref(), state.key().as_ref()],
+        bump,
+    )]
+    pub pubsup_pda: Box<Account<'info, Balance>>,
+    #[account(
+        init_if_needed,
+        payer = claimant,
+        associated_token::mint = mint,
+        associated_token::authority = pubsup_pda,
+    )]
+    pub pubsup_ata: Box<Account<'info, TokenAccount>>,
+    #[account(mut, address = state.mint)]
+    pub mint: Box<Account<'info, Mint>>,
+    ///CHECK:
+    #[account(
+        mut,
+        seeds = [VEST_SEED.as_ref(), state.key().as_ref(), [_round].as_ref(), claimant.key().as_ref()],
+        bump
+    )]
+    pub vesting: Box<Account<'info, Vesting>>,
+    #[account(
+        seeds = [ROUND_SEED.as_ref(), state.key().as_ref(), [_round].as_ref()],
+        bump,
+    )]
+    pub purchase_round: Box<Account<'info, Round>>,
+    #[account(
+        init_if_needed,
+        payer = claimant,
+        associated_token::mint = mint,
+        associated_token::authority = claimant,
+    )]
+    pub claimant_ata: Box<Account<'info, TokenAccount>>,
+    pub token_program: Program<'info, token::Token>,
+    pub system_program: Program<'info, System>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+#[instruction(round: u8)]
+pub struct StartRound<'info> {
+    ///CHECK:
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
+    )]
+    pub state: Box<Account<'info, State>>,
+    /// The currently active round, required once any round has been started.
+    #[account(
+        mut,
+        seeds = [ROUND_SEED.as_ref(), state.key().as_ref(), [state.current_active_phase].as_ref()],
+        bump,
+    )]
+    pub previous_round: Option<Box<Account<'info, Round>>>,
+    #[account(
+        mut,
+        seeds = [ROUND_SEED.as_ref(), state.key().as_ref(), [round].as_ref()],
+        bump,
+    )]
+    pub next_round: Box<Account<'info, Round>>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimAll<'info> {
+    #[account(mut)]
+    pub claimant: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
+    )]
+    pub state: Box<Account<'info, State>>,
+    #[account(
+        seeds = [PUB_POOL_SEEDS.as_ref(), state.key().as_ref()],
+        bump,
+    )]
+    pub pubsup_pda: Box<Account<'info, Balance>>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = pubsup_pda,
+    )]
+    pub pubsup_ata: Box<Account<'info, TokenAccount>>,
+    #[account(address = state.mint)]
+    pub mint: Box<Account<'info, Mint>>,
+    #[account(
+        init_if_needed,
+        payer = claimant,
+        associated_token::mint = mint,
+        associated_token::authority = claimant,
+    )]
+    pub claimant_ata: Box<Account<'info, TokenAccount>>,
+    pub token_program: Program<'info, token::Token>,
+    pub system_program: Program<'info, System>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+#[instruction(round: u8)]
+pub struct Refund<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
+    )]
+    pub state: Box<Account<'info, State>>,
+    #[account(
+        mut,
+        seeds = [VEST_SEED.as_ref(), state.key().as_ref(), [round].as_ref(), buyer.key().as_ref()],
+        bump
+    )]
+    pub vesting: Box<Account<'info, Vesting>>,
+    #[account(
+        mut,
+        seeds = [ROUND_SEED.as_ref(), state.key().as_ref(), [round].as_ref()],
+        bump,
+    )]
+    pub purchase_round: Box<Account<'info, Round>>,
+    #[account(
+        seeds = [VAULT_SEEDS.as_ref(), state.key().as_ref()],
+        bump,
+    )]
+    pub vault_pda: Box<Account<'info, Balance>>,
+    #[account(
+        mut,
+        associated_token::mint = payment_mint,
+        associated_token::authority = vault_pda,
+    )]
+    pub payment_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        associated_token::mint = payment_mint,
+        associated_token::authority = buyer,
+    )]
+    pub buyer_payment_mint_ata: Box<Account<'info, TokenAccount>>,
+    pub payment_mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        seeds = [PAYMENT_MINT_SEED.as_ref(), state.key().as_ref(), payment_mint.key().as_ref()],
+        bump,
+    )]
+    pub payment_mint_info: Box<Account<'info, PaymentMint>>,
+    #[account(
+        mut,
+        seeds = [SOL_VAULT_SEEDS.as_ref(), state.key().as_ref()],
+        bump,
+    )]
+    pub sol_vault: Box<Account<'info, Balance>>,
+    pub token_program: Program<'info, token::Token>,
+    pub system_program: Program<'info, System>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct FinalizeSale<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
+    )]
+    pub state: Box<Account<'info, State>>,
+    #[account(
+        seeds = [
+            ROUND_SEED.as_ref(),
+            state.key().as_ref(),
+            [state.round_count.saturating_sub(1)].as_ref()
+        ],
+        bump,
+    )]
+    pub last_round: Box<Account<'info, Round>>,
+    /// The currently active round, required once any round has been started.
+    #[account(
+        mut,
+        seeds = [ROUND_SEED.as_ref(), state.key().as_ref(), [state.current_active_phase].as_ref()],
+        bump,
+    )]
+    pub active_round: Option<Box<Account<'info, Round>>>,
+}
+
+#[derive(Accounts)]
+pub struct SetTge<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
+    )]
+    pub state: Box<Account<'info, State>>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawProceeds<'info> {
+    ///CHECK:
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
+    )]
+    pub state: Box<Account<'info, State>>,
+    #[account(
+        seeds = [VAULT_SEEDS.as_ref(), state.key().as_ref()],
+        bump,
+    )]
+    pub vault_pda: Box<Account<'info, Balance>>,
+    #[account(
+        mut,
+        associated_token::mint = payment_mint,
+        associated_token::authority = vault_pda,
+    )]
+    pub payment_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init_if_needed,
+        payer = authority,
+        associated_token::mint = payment_mint,
+        associated_token::authority = authority,
+    )]
+    pub authority_payment_ata: Box<Account<'info, TokenAccount>>,
+    pub payment_mint: Box<Account<'info, Mint>>,
+    #[account(
+        mut,
+        seeds = [PAYMENT_MINT_SEED.as_ref(), state.key().as_ref(), payment_mint.key().as_ref()],
+        bump,
+    )]
+    pub payment_mint_info: Box<Account<'info, PaymentMint>>,
+    #[account(
+        mut,
+        seeds = [SOL_VAULT_SEEDS.as_ref(), state.key().as_ref()],
+        bump,
+    )]
+    pub sol_vault: Box<Account<'info, Balance>>,
+    pub token_program: Program<'info, token::Token>,
+    pub system_program: Program<'info, System>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+#[instruction(kind: AllocationKind, beneficiary: Pubkey)]
+pub struct CreateAllocation<'info> {
+    #[account(mut, address = state.admin)]
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
+    )]
+    pub state: Box<Account<'info, State>>,
+    #[account(
+        init,
+        payer = admin,
+        seeds = [ALLOCATION_SEED.as_ref(), state.key().as_ref(), [kind as u8].as_ref(), beneficiary.as_ref()],
+        bump,
+        space = 8 + std::mem::size_of::<Allocation>()
+    )]
+    pub allocation: Box<Account<'info, Allocation>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(kind: AllocationKind)]
+pub struct ClaimAllocation<'info> {
+    #[account(mut)]
+    pub beneficiary: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
+    )]
+    pub state: Box<Account<'info, State>>,
+    #[account(
+        mut,
+        seeds = [ALLOCATION_SEED.as_ref(), state.key().as_ref(), [kind as u8].as_ref(), beneficiary.key().as_ref()],
+        bump,
+    )]
+    pub allocation: Box<Account<'info, Allocation>>,
+    #[account(
+        seeds = [RES_POOL_SEEDS.as_ref(), state.key().as_ref()],
+        bump,
+    )]
+    pub reserve_pda: Box<Account<'info, Balance>>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = reserve_pda,
+    )]
+    pub reserve_ata: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init_if_needed,
+        payer = beneficiary,
+        associated_token::mint = mint,
+        associated_token::authority = beneficiary,
+    )]
+    pub beneficiary_ata: Box<Account<'info, TokenAccount>>,
+    #[account(address = state.mint)]
+    pub mint: Box<Account<'info, Mint>>,
+    pub token_program: Program<'info, token::Token>,
+    pub system_program: Program<'info, System>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+#[instruction(kind: AllocationKind, beneficiary: Pubkey)]
+pub struct CancelAllocation<'info> {
+    #[account(address = state.admin)]
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
+    )]
+    pub state: Box<Account<'info, State>>,
+    #[account(
+        mut,
+        seeds = [ALLOCATION_SEED.as_ref(), state.key().as_ref(), [kind as u8].as_ref(), beneficiary.as_ref()],
+        bump,
+    )]
+    pub allocation: Box<Account<'info, Allocation>>,
+}
+
+#[derive(Accounts)]
+pub struct BurnUnsold<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
+    )]
+    pub state: Box<Account<'info, State>>,
+    #[account(
+        seeds = [PUB_POOL_SEEDS.as_ref(), state.key().as_ref()],
+        bump,
+    )]
+    pub pubsup_pda: Box<Account<'info, Balance>>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = pubsup_pda,
+    )]
+    pub pubsup_ata: Box<Account<'info, TokenAccount>>,
+    #[account(
+        seeds = [BUR_POOL_SEEDS.as_ref(), state.key().as_ref()],
+        bump,
+    )]
+    pub burn_pda: Box<Account<'info, Balance>>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = burn_pda,
+    )]
+    pub burn_pool_ata: Box<Account<'info, TokenAccount>>,
+    #[account(mut, address = state.mint)]
+    pub mint: Box<Account<'info, Mint>>,
+    pub token_program: Program<'info, token::Token>,
+}
+
+#[derive(Accounts)]
+pub struct SweepUnsoldToReserve<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
+    )]
+    pub state: Box<Account<'info, State>>,
+    #[account(
+        seeds = [PUB_POOL_SEEDS.as_ref(), state.key().as_ref()],
+        bump,
+    )]
+    pub pubsup_pda: Box<Account<'info, Balance>>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = pubsup_pda,
+    )]
+    pub pubsup_ata: Box<Account<'info, TokenAccount>>,
+    #[account(
+        seeds = [RES_POOL_SEEDS.as_ref(), state.key().as_ref()],
+        bump,
+    )]
+    pub reserve_pda: Box<Account<'info, Balance>>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = reserve_pda,
+    )]
+    pub reserve_ata: Box<Account<'info, TokenAccount>>,
+    #[account(address = state.mint)]
+    pub mint: Box<Account<'info, Mint>>,
+    pub token_program: Program<'info, token::Token>,
+}
+
+#[derive(Accounts)]
+pub struct SetUnsoldPolicy<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
+    )]
+    pub state: Box<Account<'info, State>>,
+}
+
+#[derive(Accounts)]
+pub struct SetWithdrawalDelay<'info> {
+    #[account(address = state.admin)]
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
+    )]
+    pub state: Box<Account<'info, State>>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool: Pool)]
+pub struct QueueWithdrawal<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
+    )]
+    pub state: Box<Account<'info, State>>,
+    #[account(
+        init,
+        payer = authority,
+        seeds = [WITHDRAWAL_SEED.as_ref(), state.key().as_ref(), [pool as u8].as_ref()],
+        bump,
+        space = 8 + std::mem::size_of::<PendingWithdrawal>()
+    )]
+    pub pending_withdrawal: Box<Account<'info, PendingWithdrawal>>,
+    #[account(token::mint = state.mint)]
+    pub recipient: Box<Account<'info, TokenAccount>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool: Pool)]
+pub struct CancelWithdrawal<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
+    )]
+    pub state: Box<Account<'info, State>>,
+    #[account(
+        mut,
+        close = authority,
+        seeds = [WITHDRAWAL_SEED.as_ref(), state.key().as_ref(), [pool as u8].as_ref()],
+        bump,
+    )]
+    pub pending_withdrawal: Box<Account<'info, PendingWithdrawal>>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool: Pool)]
+pub struct ExecuteWithdrawal<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [STATE_SEED.as_ref(), state.sale_id.to_le_bytes().as_ref()],
+        bump = state.bump,
+    )]
+    pub state: Box<Account<'info, State>>,
+    #[account(
+        mut,
+        close = authority,
+        seeds = [WITHDRAWAL_SEED.as_ref(), state.key().as_ref(), [pool as u8].as_ref()],
+        bump,
+    )]
+    pub pending_withdrawal: Box<Account<'info, PendingWithdrawal>>,
+    #[account(
+        seeds = [pool.seed(), state.key().as_ref()],
+        bump,
+    )]
+    pub pool_pda: Box<Account<'info, Balance>>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = pool_pda,
+    )]
+    pub pool_ata: Box<Account<'info, TokenAccount>>,
+    #[account(mut, address = pending_withdrawal.recipient)]
+    pub recipient: Box<Account<'info, TokenAccount>>,
+    #[account(address = state.mint)]
+    pub mint: Box<Account<'info, Mint>>,
+    pub token_program: Program<'info, token::Token>,
+}
+
+#[account]
+pub struct Round {
+    pub index: u8,
+    pub active: bool,
+    pub allocation_bps: u16,
+    pub price: Price,
+    pub mint_prices: [Price; MAX_PAYMENT_MINTS],
+    pub usd_price: Price,
+    pub sol_price: Price,
+    pub schedule: VestingSchedule,
+    pub balance: u64,
+    pub tokens_sold: u64,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub wallet_cap: u64,
+    pub allowlist_root: [u8; 32],
+    pub min_contribution: u64,
+    pub max_contribution: u64,
+}
+
+impl Round {
+    /// Deactivates the round at `now` and returns its unsold balance.
+    pub fn end(&mut self, sale_id: u64, now: i64) -> u64 {
+        self.active = false;
+        self.end_time = self.end_time.min(now);
+        let unsold = std::mem::take(&mut self.balance);
+
+        emit!(RoundEnded {
+            sale_id,
+            round: self.index,
+            tokens_sold: self.tokens_sold,
+            unsold,
+            end_time: self.end_time,
+        });
+        unsold
+    }
+}
+
+/// Admin-supplied schedule for one presale round. `allocation_bps` is the
+/// round's share of the public pool and `wallet_cap` (0 = uncapped) limits the
+/// sale tokens a single wallet may buy in the round. A non-zero
+/// `allowlist_root` restricts the round to wallets in that Merkle tree.
+/// `min_contribution` applies to each purchase and `max_contribution`
+/// (0 = unlimited) to a wallet's running total, both in payment-mint base units.
+/// `sol_price` is quoted in lamports; a zero numerator disables SOL purchases.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct RoundConfig {
+    pub allocation_bps: u16,
+    pub price: Price,
+    pub usd_price: Price,
+    pub sol_price: Price,
+    pub schedule: VestingSchedule,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub wallet_cap: u64,
+    pub allowlist_root: [u8; 32],
+    pub min_contribution: u64,
+    pub max_contribution: u64,
+}
+
+/// Exact round price: `numerator` payment-mint base units buy `denominator`
+/// sale-token base units.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default)]
+pub struct Price {
+    pub numerator: u64,
+    pub denominator: u64,
+}
+
+impl Price {
+    /// Sale-token base units bought by `pay_amount`, rounded down.
+    pub fn tokens_for(&self, pay_amount: u64) -> Result<u64> {
+        require!(
+            self.numerator > 0 && self.denominator > 0,
+            CustomError::InvalidPrice
+        );
+        let tokens = (pay_amount as u128)
+            .checked_mul(self.denominator as u128)
+            .ok_or(CustomError::MathOverflow)?
+            / self.numerator as u128;
+        u64::try_from(tokens).map_err(|_| CustomError::MathOverflow.into())
+    }
+
+    /// Base units of the quoted currency needed to buy `tokens`, rounded up.
+    pub fn cost_of(&self, tokens: u64) -> Result<u64> {
+        require!(
+            self.numerator > 0 && self.denominator > 0,
+            CustomError::InvalidPrice
+        );
+        let cost = (tokens as u128)
+            .checked_mul(self.numerator as u128)
+            .and_then(|v| v.checked_add(self.denominator as u128 - 1))
+            .ok_or(CustomError::MathOverflow)?
+            / self.denominator as u128;
+        u64::try_from(cost).map_err(|_| CustomError::MathOverflow.into())
+    }
+}
+
+/// Oracle price of one whole payment token: `price * 10^expo` USD.
+pub struct OraclePrice {
+    pub price: u64,
+    pub expo: i32,
+}
+
+impl OraclePrice {
+    /// Converts a micro-USD `usd_price` into a price in base units of a payment
+    /// mint with `decimals` decimals.
+    pub fn quote(&self, usd_price: &Price, decimals: u8) -> Result<Price> {
+        // One payment base unit is worth `price * 10^shift` micro-USD
+        let shift = self.expo + USD_DECIMALS - decimals as i32;
+        let scale = 10u128
+            .checked_pow(shift.unsigned_abs())
+            .ok_or(CustomError::MathOverflow)?;
+        let (numerator, denominator) = if shift >= 0 {
+            (
+                Some(usd_price.numerator as u128),
+                (usd_price.denominator as u128)
+                    .checked_mul(self.price as u128)
+                    .and_then(|v| v.checked_mul(scale)),
+            )
+        } else {
+            (
+                (usd_price.numerator as u128).checked_mul(scale),
+                (usd_price.denominator as u128).checked_mul(self.price as u128),
+            )
+        };
+        let mut numerator = numerator.ok_or(CustomError::MathOverflow)?;
+        let mut denominator = denominator.ok_or(CustomError::MathOverflow)?;
+
+        // Reduce the ratio so it fits a `Price`
+        let (mut a, mut b) = (numerator, denominator);
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        numerator /= a;
+        denominator /= a;
+
+        Ok(Price {
+            numerator: u64::try_from(numerator).map_err(|_| CustomError::MathOverflow)?,
+            denominator: u64::try_from(denominator).map_err(|_| CustomError::MathOverflow)?,
+        })
+    }
+}
+
+/// Release schedule for purchased tokens: `tge_unlock_bps` is available at
+/// TGE, the rest vests linearly over `vesting_duration` seconds after a
+/// `cliff_duration`, unlocking in steps of `release_interval` seconds.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default)]
+pub struct VestingSchedule {
+    pub tge_unlock_bps: u16,
+    pub cliff_duration: i64,
+    pub vesting_duration: i64,
+    pub release_interval: i64,
+}
+
+impl VestingSchedule {
+    pub fn validate(&self) -> Result<()> {
+        require!(
+            self.tge_unlock_bps as u64 <= BPS_DENOMINATOR
+                && self.cliff_duration >= 0
+                && self.vesting_duration >= 0
+                && self.release_interval >= 0
+                && self.release_interval <= self.vesting_duration,
+            CustomError::InvalidVestingSchedule
+        );
+        Ok(())
+    }
+
+    /// Portion of `total` vested at `now` for a schedule starting at `start`.
+    pub fn vested_amount(&self, total: u64, start: i64, now: i64) -> Result<u64> {
+        if now < start {
+            return Ok(0);
+        }
+        let unlocked_at_tge = bps_of(total, self.tge_unlock_bps)?;
+        let elapsed = now - start;
+        if elapsed < self.cliff_duration {
+            return Ok(unlocked_at_tge);
+        }
+        let linear_elapsed = elapsed - self.cliff_duration;
+        if linear_elapsed >= self.vesting_duration {
+            return Ok(total);
+        }
+
+        // Only whole release intervals count towards the linear portion
+        let interval = self.release_interval.max(1);
+        let released_time = linear_elapsed / interval * interval;
+        let linear_vested = ((total - unlocked_at_tge) as u128)
+            .checked_mul(released_time as u128)
+            .ok_or(CustomError::MathOverflow)?
+            / self.vesting_duration as u128;
+        unlocked_at_tge
+            .checked_add(linear_vested as u64)
+            .ok_or(CustomError::MathOverflow.into())
+    }
+}
+
+#[account]
+pub struct State {
+    pub sale_id: u64,
+    pub bump: u8,
+    pub admin: Pubkey,
+    pub pending_admin: Pubkey,
+    pub operator: Pubkey,
+    pub treasurer: Pubkey,
+    pub pauser: Pubkey,
+    pub paused: u8,
+    pub withdrawal_delay: i64,
+    pub payment_mint: Pubkey,
+    pub mint: Pubkey,
+    pub pub_supply: u64,
     pub reserve_supply: u64,
-    pub rounds: [Round; 3],
+    pub public_pool_bps: u16,
+    pub allocated_bps: u16,
+    pub round_count: u8,
+    pub round_supply: u64,
+    pub round_supply_allocated: u64,
     pub current_active_phase: u8,
-    pub admin_remaining_tokens: u64,
+    pub status: SaleStatus,
+    pub total_raised: u64,
+    pub soft_cap: u64,
+    pub hard_cap: u64,
+    pub total_refunded: u64,
+    pub tge_timestamp: i64,
+    pub total_raised_sol: u64,
+    pub payment_mint_count: u8,
+    pub total_owed_to_buyers: u64,
+    pub reserve_owed: u64,
+    pub burn_pool_bps: u16,
+    pub burn_supply: u64,
+    pub burned_supply: u64,
+    pub unsold_policy: UnsoldPolicy,
+    pub unsold_to_burn: u64,
+    pub unsold_to_reserve: u64,
+}
+
+impl State {
+    /// Whether `key` may act as `role`. The admin holds every role.
+    pub fn has_role(&self, role: Role, key: &Pubkey) -> bool {
+        *key == self.admin || (*key != Pubkey::default() && *key == self.role(role))
+    }
+
+    pub fn role(&self, role: Role) -> Pubkey {
+        match role {
+            Role::Operator => self.operator,
+            Role::Treasurer => self.treasurer,
+            Role::Pauser => self.pauser,
+        }
+    }
+
+    fn role_mut(&mut self, role: Role) -> &mut Pubkey {
+        match role {
+            Role::Operator => &mut self.operator,
+            Role::Treasurer => &mut self.treasurer,
+            Role::Pauser => &mut self.pauser,
+        }
+    }
+
+    /// Fails with `SalePaused` while any of `flags` is paused.
+    pub fn check_not_paused(&self, flags: u8) -> Result<()> {
+        require!(self.paused & flags == 0, CustomError::SalePaused);
+        Ok(())
+    }
+
+    /// Applies the sale's unsold policy to `unsold` tokens from a round that
+    /// just ended. `next_round` is the round starting in its place, if any;
+    /// with none, rolled-over tokens return to the public pool instead.
+    pub fn release_unsold(&mut self, unsold: u64, next_round: Option<&mut Round>) -> Result<()> {
+        let target = match (self.unsold_policy, next_round) {
+            (UnsoldPolicy::Rollover, Some(next_round)) => &mut next_round.balance,
+            (UnsoldPolicy::ReturnToPublic | UnsoldPolicy::Rollover, _) => &mut self.pub_supply,
+            (UnsoldPolicy::Burn, _) => &mut self.unsold_to_burn,
+            (UnsoldPolicy::ReturnToReserve, _) => &mut self.unsold_to_reserve,
+        };
+        *target = target
+            .checked_add(unsold)
+            .ok_or(CustomError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Ends the sale, entering refund mode if the soft cap was not reached.
+    pub fn close_sale(&mut self) {
+        self.status = if self.total_raised < self.soft_cap {
+            SaleStatus::Refunding
+        } else {
+            SaleStatus::Finalized
+        };
+
+        emit!(SaleClosed {
+            sale_id: self.sale_id,
+            status: self.status,
+            total_raised: self.total_raised,
+            total_raised_sol: self.total_raised_sol,
+        });
+    }
+}
+
+/// Operational roles the admin can delegate. The admin itself is the
+/// super-admin: it holds every role and alone can grant or revoke them.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    /// Configures and runs rounds: prices, allowlists, round starts, TGE.
+    Operator,
+    /// Withdraws proceeds and leftover tokens.
+    Treasurer,
+    /// Pauses and unpauses the sale.
+    Pauser,
+}
+
+/// What happens to a round's unsold tokens when it ends.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnsoldPolicy {
+    /// Add them to the unallocated public supply.
+    ReturnToPublic,
+    /// Add them to the next round's balance.
+    Rollover,
+    /// Set them aside for `burn_unsold`.
+    Burn,
+    /// Set them aside for `sweep_unsold_to_reserve`.
+    ReturnToReserve,
+}
+
+/// Token pool held by the sale.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pool {
+    Public,
+    Reserve,
+}
+
+impl Pool {
+    /// Seed of the PDA that owns the pool's token account.
+    pub fn seed(&self) -> &'static [u8] {
+        match self {
+            Pool::Public => PUB_POOL_SEEDS,
+            Pool::Reserve => RES_POOL_SEEDS,
+        }
+    }
+}
+
+/// Purpose of a reserve allocation.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AllocationKind {
+    Team,
+    Advisors,
+    Marketing,
+    Liquidity,
+}
+
+/// Share of the reserve pool owed to a beneficiary, vesting from TGE under
+/// its own schedule.
+#[account]
+pub struct Allocation {
+    pub kind: AllocationKind,
+    pub beneficiary: Pubkey,
+    pub amount: u64,
+    pub claimed_amount: u64,
+    pub schedule: VestingSchedule,
+}
+
+/// Pool withdrawal waiting out the sale's withdrawal delay. Anyone can read it
+/// before it executes.
+#[account]
+pub struct PendingWithdrawal {
+    pub pool: Pool,
+    pub amount: u64,
+    pub recipient: Pubkey,
+    pub queued_by: Pubkey,
+    pub queued_at: i64,
+    pub executable_at: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SaleStatus {
+    Active,
+    Finalized,
+    Refunding,
 }
 
 #[account]
 pub struct Vesting {
     pub owner: Pubkey,
+    pub total_purchased: u64,
+    pub total_contributed: u64,
     pub purchases: Vec<Purchase>,
 }
 
 impl Vesting {
-    pub const MAX_SIZE: usize = 8 + 32 + (8 + 8 + 1) * 100;
+    pub const MAX_SIZE: usize = 32 + 8 + 8 + 4 + Purchase::SIZE * MAX_PURCHASES;
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone)]
 pub struct Purchase {
     pub amount: u64,
+    pub paid: u64,
+    pub value: u64,
+    pub currency: Currency,
     pub start_time: i64,
     pub round: u8,
-    pub claimed: bool,
+    pub claimed_amount: u64,
+}
+
+/// Currency a purchase was paid in; SPL payments carry the payment mint's
+/// registry index.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Currency {
+    Spl(u8),
+    Sol,
+}
+
+impl Purchase {
+    pub const SIZE: usize = 8 + 8 + 8 + 2 + 8 + 1 + 8;
+
+    /// Marks everything vested at `now` as claimed and returns the newly claimable amount.
+    pub fn claim_vested(&mut self, schedule: &VestingSchedule, start: i64, now: i64) -> Result<u64> {
+        let vested_amount = schedule.vested_amount(self.amount, start, now)?;
+        let claimable_amount = vested_amount.saturating_sub(self.claimed_amount);
+        self.claimed_amount = self.claimed_amount.max(vested_amount);
+        Ok(claimable_amount)
+    }
+}
+
+/// Accepted payment mint registered for a sale, with the proceeds it has
+/// escrowed. Round prices for it live at `Round.mint_prices[index]`.
+#[account]
+pub struct PaymentMint {
+    pub mint: Pubkey,
+    pub decimals: u8,
+    pub index: u8,
+    pub total_raised: u64,
+    pub total_refunded: u64,
+    pub total_withdrawn: u64,
+    pub price_feed: Pubkey,
+    pub max_price_age: i64,
+    pub max_confidence_bps: u16,
 }
 
 #[account]
@@ -790,6 +3171,268 @@ pub struct Balance {
     pub balance: u64,
 }
 
+#[event]
+pub struct SaleInitialized {
+    pub sale_id: u64,
+    pub admin: Pubkey,
+    pub mint: Pubkey,
+    pub payment_mint: Pubkey,
+    pub public_pool_bps: u16,
+    pub burn_pool_bps: u16,
+    pub soft_cap: u64,
+    pub hard_cap: u64,
+}
+
+#[event]
+pub struct AdminProposed {
+    pub sale_id: u64,
+    pub admin: Pubkey,
+    pub pending_admin: Pubkey,
+}
+
+#[event]
+pub struct AdminTransferred {
+    pub sale_id: u64,
+    pub previous_admin: Pubkey,
+    pub new_admin: Pubkey,
+}
+
+#[event]
+pub struct RoleGranted {
+    pub sale_id: u64,
+    pub role: Role,
+    pub account: Pubkey,
+}
+
+#[event]
+pub struct RoleRevoked {
+    pub sale_id: u64,
+    pub role: Role,
+    pub account: Pubkey,
+}
+
+#[event]
+pub struct PauseToggled {
+    pub sale_id: u64,
+    pub authority: Pubkey,
+    pub previous: u8,
+    pub paused: u8,
+}
+
+#[event]
+pub struct PaymentMintAdded {
+    pub sale_id: u64,
+    pub mint: Pubkey,
+    pub index: u8,
+    pub decimals: u8,
+}
+
+#[event]
+pub struct MintPriceSet {
+    pub sale_id: u64,
+    pub round: u8,
+    pub mint: Pubkey,
+    pub price: Price,
+}
+
+#[event]
+pub struct PriceFeedSet {
+    pub sale_id: u64,
+    pub mint: Pubkey,
+    pub price_feed: Pubkey,
+    pub max_price_age: i64,
+    pub max_confidence_bps: u16,
+}
+
+#[event]
+pub struct RoundAdded {
+    pub sale_id: u64,
+    pub round: u8,
+    pub allocation_bps: u16,
+    pub start_time: i64,
+    pub end_time: i64,
+}
+
+#[event]
+pub struct AllowlistRootSet {
+    pub sale_id: u64,
+    pub round: u8,
+    pub allowlist_root: [u8; 32],
+}
+
+#[event]
+pub struct TokensDistributed {
+    pub sale_id: u64,
+    pub total_supply: u64,
+    pub public_supply: u64,
+    pub reserve_supply: u64,
+    pub burn_supply: u64,
+}
+
+#[event]
+pub struct RoundStarted {
+    pub sale_id: u64,
+    pub round: u8,
+    pub allocation: u64,
+    pub start_time: i64,
+    pub end_time: i64,
+}
+
+#[event]
+pub struct RoundEnded {
+    pub sale_id: u64,
+    pub round: u8,
+    pub tokens_sold: u64,
+    pub unsold: u64,
+    pub end_time: i64,
+}
+
+#[event]
+pub struct TokensPurchased {
+    pub sale_id: u64,
+    pub buyer: Pubkey,
+    pub round: u8,
+    pub purchase_index: u64,
+    pub currency: Currency,
+    pub tokens: u64,
+    pub paid: u64,
+    pub value: u64,
+    pub total_raised: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TokensClaimed {
+    pub sale_id: u64,
+    pub claimant: Pubkey,
+    pub round: u8,
+    pub purchase_index: u64,
+    pub amount: u64,
+    pub claimed_amount: u64,
+    pub total_amount: u64,
+}
+
+#[event]
+pub struct AllClaimed {
+    pub sale_id: u64,
+    pub claimant: Pubkey,
+    pub total: u64,
+    pub purchases: u64,
+}
+
+#[event]
+pub struct Refunded {
+    pub sale_id: u64,
+    pub buyer: Pubkey,
+    pub round: u8,
+    pub payment_mint: Pubkey,
+    pub amount: u64,
+    pub lamports: u64,
+    pub value: u64,
+    pub tokens_returned: u64,
+}
+
+#[event]
+pub struct SaleClosed {
+    pub sale_id: u64,
+    pub status: SaleStatus,
+    pub total_raised: u64,
+    pub total_raised_sol: u64,
+}
+
+#[event]
+pub struct TgeSet {
+    pub sale_id: u64,
+    pub tge_timestamp: i64,
+}
+
+#[event]
+pub struct ProceedsWithdrawn {
+    pub sale_id: u64,
+    pub authority: Pubkey,
+    pub currency: Currency,
+    pub amount: u64,
+}
+
+#[event]
+pub struct AllocationCreated {
+    pub sale_id: u64,
+    pub kind: AllocationKind,
+    pub beneficiary: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct AllocationClaimed {
+    pub sale_id: u64,
+    pub kind: AllocationKind,
+    pub beneficiary: Pubkey,
+    pub amount: u64,
+    pub claimed_amount: u64,
+    pub total_amount: u64,
+}
+
+#[event]
+pub struct AllocationCancelled {
+    pub sale_id: u64,
+    pub kind: AllocationKind,
+    pub beneficiary: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct TokensBurned {
+    pub sale_id: u64,
+    pub unsold: u64,
+    pub burn_pool: u64,
+    pub burned_supply: u64,
+}
+
+#[event]
+pub struct UnsoldPolicySet {
+    pub sale_id: u64,
+    pub unsold_policy: UnsoldPolicy,
+}
+
+#[event]
+pub struct UnsoldSweptToReserve {
+    pub sale_id: u64,
+    pub amount: u64,
+    pub reserve_supply: u64,
+}
+
+#[event]
+pub struct WithdrawalDelaySet {
+    pub sale_id: u64,
+    pub withdrawal_delay: i64,
+}
+
+#[event]
+pub struct WithdrawalQueued {
+    pub sale_id: u64,
+    pub pool: Pool,
+    pub amount: u64,
+    pub recipient: Pubkey,
+    pub queued_by: Pubkey,
+    pub executable_at: i64,
+}
+
+#[event]
+pub struct WithdrawalCancelled {
+    pub sale_id: u64,
+    pub pool: Pool,
+    pub amount: u64,
+    pub cancelled_by: Pubkey,
+}
+
+#[event]
+pub struct PoolTokensWithdrawn {
+    pub sale_id: u64,
+    pub authority: Pubkey,
+    pub pool: Pool,
+    pub amount: u64,
+}
+
 #[error_code]
 pub enum CustomError {
     #[msg("The round has expired.")]
@@ -810,4 +3453,94 @@ pub enum CustomError {
     AlreadyClaimed,
     #[msg("Only admin can perform this action.")]
     Unauthorized,
+    #[msg("Arithmetic overflow.")]
+    MathOverflow,
+    #[msg("Invalid price: both the numerator and denominator must be set.")]
+    InvalidPrice,
+    #[msg("Payment amount is too small to buy any tokens.")]
+    PurchaseTooSmall,
+    #[msg("Allocations must sum to 10,000 basis points.")]
+    InvalidAllocation,
+    #[msg("Invalid round configuration.")]
+    InvalidRoundConfig,
+    #[msg("The round has not started yet.")]
+    RoundNotStarted,
+    #[msg("Purchase exceeds the per-wallet cap for this round.")]
+    WalletCapExceeded,
+    #[msg("Tokens have already been distributed.")]
+    AlreadyDistributed,
+    #[msg("Tokens have not been distributed yet.")]
+    NotDistributed,
+    #[msg("The sale is not active.")]
+    SaleNotActive,
+    #[msg("The sale has not been finalized.")]
+    SaleNotFinalized,
+    #[msg("The sale is refunding; claims are disabled.")]
+    SaleRefunding,
+    #[msg("The sale is not in refund mode.")]
+    SaleNotRefunding,
+    #[msg("Nothing to refund.")]
+    NothingToRefund,
+    #[msg("The hard cap must be at least the soft cap.")]
+    InvalidCap,
+    #[msg("Invalid vesting schedule.")]
+    InvalidVestingSchedule,
+    #[msg("No vested tokens are available to claim.")]
+    NothingToClaim,
+    #[msg("Remaining accounts must be this sale's (vesting, round) pairs.")]
+    InvalidRemainingAccounts,
+    #[msg("Tokens unlock at TGE, which has not been reached.")]
+    TgeNotReached,
+    #[msg("The TGE timestamp has already been set.")]
+    TgeAlreadySet,
+    #[msg("Invalid TGE timestamp.")]
+    InvalidTgeTimestamp,
+    #[msg("Wallet is not on the round's allowlist.")]
+    NotAllowlisted,
+    #[msg("Purchase exceeds the wallet's allowlist allocation.")]
+    AllocationExceeded,
+    #[msg("Purchase is below the round's minimum contribution.")]
+    BelowMinContribution,
+    #[msg("Purchase exceeds the per-wallet maximum contribution for this round.")]
+    ExceedsMaxContribution,
+    #[msg("Too many purchases recorded for this wallet in this round.")]
+    TooManyPurchases,
+    #[msg("Purchase would receive fewer tokens than the requested minimum.")]
+    SlippageExceeded,
+    #[msg("SOL payments are not enabled for this round.")]
+    SolPaymentsDisabled,
+    #[msg("The round has no price for this payment mint.")]
+    PaymentMintNotPriced,
+    #[msg("Too many payment mints registered.")]
+    TooManyPaymentMints,
+    #[msg("Invalid payment mint.")]
+    InvalidPaymentMint,
+    #[msg("Invalid price feed account.")]
+    InvalidPriceFeed,
+    #[msg("Invalid oracle configuration.")]
+    InvalidOracleConfig,
+    #[msg("Oracle price is not trading.")]
+    InvalidOraclePrice,
+    #[msg("Oracle price is stale.")]
+    StaleOraclePrice,
+    #[msg("Oracle price confidence is too wide.")]
+    OracleConfidenceTooWide,
+    #[msg("This part of the sale is paused.")]
+    SalePaused,
+    #[msg("Invalid pause flags.")]
+    InvalidPauseFlags,
+    #[msg("Invalid withdrawal delay.")]
+    InvalidWithdrawalDelay,
+    #[msg("The withdrawal is still timelocked.")]
+    WithdrawalLocked,
+    #[msg("Amount must be greater than zero.")]
+    InvalidAmount,
+    #[msg("Not enough unallocated reserve supply.")]
+    InsufficientReserve,
+    #[msg("Nothing to burn.")]
+    NothingToBurn,
+    #[msg("The sale has not closed yet.")]
+    SaleNotClosed,
+    #[msg("The allocation has fully vested.")]
+    AllocationFullyVested,
 }
\ No newline at end of file
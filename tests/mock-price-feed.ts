@@ -0,0 +1,36 @@
+import * as anchor from "@coral-xyz/anchor";
+
+// Address the tests write the mock feed to
+export const MOCK_PRICE_FEED = new anchor.web3.PublicKey("E3nY7iMM4x3mayVQKhQPRtatXC1Xsni6DZx31KWjy1F6");
+
+// Pyth oracle program the program's default (non-devnet) build accepts feeds from
+export const PYTH_PROGRAM_ID = new anchor.web3.PublicKey("FsJ3A3u2vn5cTVofAjvy6y5kwABJAqYWpe4975bi2epH");
+
+const PYTH_MAGIC = 0xa1b2c3d4;
+const PYTH_VERSION = 2;
+const PYTH_PRICE_ACCOUNT_TYPE = 3;
+const PYTH_STATUS_TRADING = 1;
+const PYTH_PRICE_ACCOUNT_LEN = 3312;
+
+export interface MockPrice {
+  price: bigint;
+  conf: bigint;
+  expo: number;
+  timestamp: bigint;
+  status?: number;
+}
+
+// Encodes a Pyth v2 price account with only the fields the program reads set
+export function encodePriceFeed({ price, conf, expo, timestamp, status = PYTH_STATUS_TRADING }: MockPrice): Buffer {
+  const data = Buffer.alloc(PYTH_PRICE_ACCOUNT_LEN);
+  data.writeUInt32LE(PYTH_MAGIC, 0);
+  data.writeUInt32LE(PYTH_VERSION, 4);
+  data.writeUInt32LE(PYTH_PRICE_ACCOUNT_TYPE, 8);
+  data.writeUInt32LE(PYTH_PRICE_ACCOUNT_LEN, 12);
+  data.writeInt32LE(expo, 20);
+  data.writeBigInt64LE(timestamp, 96);
+  data.writeBigInt64LE(price, 208);
+  data.writeBigUInt64LE(conf, 216);
+  data.writeUInt32LE(status, 224);
+  return data;
+}
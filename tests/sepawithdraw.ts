@@ -1,10 +1,14 @@
 import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
-import { Sepawithdraw } from "../target/types/sepawithdraw";
-import { getAssociatedTokenAddressSync, createAssociatedTokenAccountInstruction, TOKEN_PROGRAM_ID, ASSOCIATED_TOKEN_PROGRAM_ID, createTransferInstruction } from "@solana/spl-token";
+import { IDL, Sepawithdraw } from "../target/types/sepawithdraw";
+import { getAssociatedTokenAddressSync, createAssociatedTokenAccountInstruction, TOKEN_PROGRAM_ID, ASSOCIATED_TOKEN_PROGRAM_ID, createTransferInstruction, createAssociatedTokenAccountIdempotentInstruction, createMintToInstruction, AccountLayout, MintLayout, MINT_SIZE } from "@solana/spl-token";
 import * as bs58 from "bs58";
 import { assert } from "chai";
 import { utf8 } from "@coral-xyz/anchor/dist/cjs/utils/bytes";
+import { BankrunProvider } from "anchor-bankrun";
+import { Clock, ProgramTestContext, startAnchor } from "solana-bankrun";
+import { keccak_256 } from "@noble/hashes/sha3";
+import { encodePriceFeed, MOCK_PRICE_FEED, PYTH_PROGRAM_ID } from "./mock-price-feed";
 
 const log = console.log;
 
@@ -19,12 +23,27 @@ describe("sepawithdraw", () => {
   const PUB_POOL_SEEDS = utf8.encode("public_pool");
   const RES_POOL_SEEDS = utf8.encode("reserve_pool");
   const BUR_POOL_SEEDS = utf8.encode("burn_pool");
+  const VAULT_SEEDS = utf8.encode("payment_vault");
+  const SOL_VAULT_SEEDS = utf8.encode("sol_vault");
   const VEST_SEED = utf8.encode("vesting");
+  const ROUND_SEED = utf8.encode("round");
+  const PAYMENT_MINT_SEED = utf8.encode("payment_mint");
+  const WITHDRAWAL_SEED = utf8.encode("withdrawal");
+  const ALLOCATION_SEED = utf8.encode("allocation");
   const TOKEN_SUPPLY = 999_000_000_000_000;
+  const SALE_ID = new anchor.BN(0);
 
-  const [state_pda, state_bump] = anchor.web3.PublicKey.findProgramAddressSync([STATE_SEEDS], program.programId);
-  const [pubsup_pda, pubsup_bump] = anchor.web3.PublicKey.findProgramAddressSync([PUB_POOL_SEEDS, admin.toBuffer()], program.programId);
-  const [reserve_pool_pda, reserve_bump] = anchor.web3.PublicKey.findProgramAddressSync([RES_POOL_SEEDS, admin.toBuffer()], program.programId);
+  const [state_pda, state_bump] = anchor.web3.PublicKey.findProgramAddressSync([STATE_SEEDS, SALE_ID.toArrayLike(Buffer, "le", 8)], program.programId);
+  const [pubsup_pda, pubsup_bump] = anchor.web3.PublicKey.findProgramAddressSync([PUB_POOL_SEEDS, state_pda.toBuffer()], program.programId);
+  const [reserve_pool_pda, reserve_bump] = anchor.web3.PublicKey.findProgramAddressSync([RES_POOL_SEEDS, state_pda.toBuffer()], program.programId);
+  const [burn_pool_pda, burn_bump] = anchor.web3.PublicKey.findProgramAddressSync([BUR_POOL_SEEDS, state_pda.toBuffer()], program.programId);
+  const [vault_pda, vault_bump] = anchor.web3.PublicKey.findProgramAddressSync([VAULT_SEEDS, state_pda.toBuffer()], program.programId);
+  const [sol_vault, sol_vault_bump] = anchor.web3.PublicKey.findProgramAddressSync([SOL_VAULT_SEEDS, state_pda.toBuffer()], program.programId);
+  const roundPda = (index: number) =>
+    anchor.web3.PublicKey.findProgramAddressSync([ROUND_SEED, state_pda.toBuffer(), Buffer.from([index])], program.programId)[0];
+
+  const paymentMintPda = (mint: anchor.web3.PublicKey) =>
+    anchor.web3.PublicKey.findProgramAddressSync([PAYMENT_MINT_SEED, state_pda.toBuffer(), mint.toBuffer()], program.programId)[0];
 
   const secretKey = bs58.decode("2WMkZCsM35kCzTyDqwGWZEpm92zsdRgvHkEiSFR3Rj4uLsNd9Gxd3bJddZcBt1BPZRXodWwSU82vFVGnmCwehxQf");
   // const secretKey = bs58.decode("4thJdTGEioqQbqLGqGn1dBWddpkzyMokJ2yWcqdHefTRBBfzuDFC3tndKoCGQLAjDQyZLXxEuwWSvhbZDseTR3Ha");
@@ -53,17 +72,50 @@ describe("sepawithdraw", () => {
   }
 
   // it("Is initialized!", async () => {
-  //   const tx = await program.methods.initialize().accounts({
+  //   const now = Math.floor(Date.now() / 1000);
+  //   const day = 24 * 60 * 60;
+  //   const rounds = [6000, 2000, 2000].map((allocationBps, i) => ({
+  //     allocationBps,
+  //     price: { numerator: new anchor.BN(2 + i), denominator: new anchor.BN(1000) },
+  //     usdPrice: { numerator: new anchor.BN(2 + i), denominator: new anchor.BN(1000) },
+  //     solPrice: { numerator: new anchor.BN(0), denominator: new anchor.BN(1) },
+  //     schedule: {
+  //       tgeUnlockBps: 1000,
+  //       cliffDuration: new anchor.BN(0),
+  //       vestingDuration: new anchor.BN(30 * day),
+  //       releaseInterval: new anchor.BN(day),
+  //     },
+  //     startTime: new anchor.BN(now + i * day),
+  //     endTime: new anchor.BN(now + (i + 1) * day),
+  //     walletCap: new anchor.BN(0),
+  //     allowlistRoot: Array(32).fill(0),
+  //     minContribution: new anchor.BN(0),
+  //     maxContribution: new anchor.BN(0),
+  //   }));
+  //   const tx = await program.methods.initialize(SALE_ID, 5000, 3000, new anchor.BN(0), new anchor.BN(0), new anchor.BN(2 * day)).accounts({
   //     admin: admin,
   //     mint: tokenMint,
   //     paymentMint: paymentMint,
   //     state: state_pda,
   //     pubsupPda: pubsup_pda,
   //     reservePda: reserve_pool_pda,
+  //     burnPda: burn_pool_pda,
+  //     vaultPda: vault_pda,
+  //     solVault: sol_vault,
+  //     paymentMintInfo: paymentMintPda(paymentMint),
   //     systemProgram: anchor.web3.SystemProgram.programId,
   //     tokenProgram: TOKEN_PROGRAM_ID,
   //   }).instruction();
   //   txis.push(tx);
+  //   for (const [i, config] of rounds.entries()) {
+  //     txis.push(await program.methods.addRound(config).accounts({
+  //       authority: admin,
+  //       state: state_pda,
+  //       previousRound: i > 0 ? roundPda(i - 1) : null,
+  //       round: roundPda(i),
+  //       systemProgram: anchor.web3.SystemProgram.programId,
+  //     }).instruction());
+  //   }
   //   const transaction = new anchor.web3.Transaction().add(...txis);
   //   const sign = await provider.sendAndConfirm(transaction)
   //     .catch((sendTxError) => {
@@ -78,6 +130,7 @@ describe("sepawithdraw", () => {
          const adminAta = await getorcreateTokenAccount(tokenMint, admin);
          const pubsupAta = await getorcreateTokenAccount(tokenMint, pubsup_pda, true);
          const reserveAta = await getorcreateTokenAccount(tokenMint, reserve_pool_pda, true);
+         const burnAta = await getorcreateTokenAccount(tokenMint, burn_pool_pda, true);
   
          const tx = await program.methods.dogdistribution(new anchor.BN(TOKEN_SUPPLY)).accounts({
            admin: admin,
@@ -88,6 +141,8 @@ describe("sepawithdraw", () => {
            pubsupAta: pubsupAta,
            reservePda: reserve_pool_pda,
            reservePoolAta: reserveAta,
+           burnPda: burn_pool_pda,
+           burnPoolAta: burnAta,
            systemProgram: anchor.web3.SystemProgram.programId,
            associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
            tokenProgram: TOKEN_PROGRAM_ID,
@@ -111,4 +166,980 @@ describe("sepawithdraw", () => {
           //  assert.equal(state.rounds[1].balance.toString(), "15000000000000", "Round 2 supply should be set correctly");
           //  assert.equal(state.rounds[2].balance.toString(), "15000000000000", "Round 3 supply should be set correctly");
      });
+
+  // Runs sales on an in-process bank so tests can write accounts and control time
+  describe("local bank", () => {
+    const day = 24 * 60 * 60;
+    const SALE_SUPPLY = 10_000_000;
+    let context: ProgramTestContext;
+    let bankProvider: BankrunProvider;
+    let bank: Program<Sepawithdraw>;
+    let authority: anchor.web3.PublicKey;
+    let adminAta: anchor.web3.PublicKey;
+    const saleMint = anchor.web3.Keypair.generate().publicKey;
+    const usdcMint = anchor.web3.Keypair.generate().publicKey;
+    let nextSaleId = 1;
+
+    interface SaleOptions {
+      softCap?: number;
+      hardCap?: number;
+      burnPoolBps?: number;
+      withdrawalDelay?: number;
+      rounds?: RoundOptions[];
+    }
+
+    interface RoundOptions {
+      schedule?: ReturnType<typeof schedule>;
+      allowlistRoot?: Buffer;
+      minContribution?: number;
+      maxContribution?: number;
+      usdPrice?: number;
+      solPrice?: number;
+    }
+
+    interface Sale {
+      id: anchor.BN;
+      state: anchor.web3.PublicKey;
+      pubsup: anchor.web3.PublicKey;
+      reserve: anchor.web3.PublicKey;
+      burn: anchor.web3.PublicKey;
+      vault: anchor.web3.PublicKey;
+      solVault: anchor.web3.PublicKey;
+      round: (index: number) => anchor.web3.PublicKey;
+      paymentMintInfo: (mint: anchor.web3.PublicKey) => anchor.web3.PublicKey;
+      vesting: (round: number, buyer: anchor.web3.PublicKey) => anchor.web3.PublicKey;
+      withdrawal: (pool: number) => anchor.web3.PublicKey;
+      allocation: (kind: number, beneficiary: anchor.web3.PublicKey) => anchor.web3.PublicKey;
+    }
+
+    interface FinalizeOptions {
+      activeRound?: number;
+      signer?: anchor.web3.Keypair;
+    }
+
+    interface BuyOptions {
+      round?: number;
+      maxAllocation?: number;
+      proof?: Buffer[];
+      minTokensOut?: number;
+      paymentMint?: anchor.web3.PublicKey;
+      priceFeed?: anchor.web3.PublicKey;
+    }
+
+    // `State.paused` flags
+    const PAUSE_PURCHASES = 1 << 0;
+    const PAUSE_CLAIMS = 1 << 1;
+    const PAUSE_REFUNDS = 1 << 2;
+
+    const poolAta = (owner: anchor.web3.PublicKey) => getAssociatedTokenAddressSync(saleMint, owner, true);
+
+    const schedule = (tgeUnlockBps: number, cliffDuration: number, vestingDuration: number, releaseInterval: number) => ({
+      tgeUnlockBps,
+      cliffDuration: new anchor.BN(cliffDuration),
+      vestingDuration: new anchor.BN(vestingDuration),
+      releaseInterval: new anchor.BN(releaseInterval),
+    });
+
+    // Allowlist leaves are keccak(wallet || max allocation), paired in sorted order
+    const allowlistLeaf = (wallet: anchor.web3.PublicKey, maxAllocation: number) =>
+      Buffer.from(keccak_256(Buffer.concat([wallet.toBuffer(), new anchor.BN(maxAllocation).toArrayLike(Buffer, "le", 8)])));
+    const hashPair = (a: Buffer, b: Buffer) =>
+      Buffer.from(keccak_256(Buffer.compare(a, b) <= 0 ? Buffer.concat([a, b]) : Buffer.concat([b, a])));
+
+    const findPda = (seeds: Uint8Array[]) => anchor.web3.PublicKey.findProgramAddressSync(seeds, program.programId)[0];
+
+    function salePdas(id: anchor.BN): Sale {
+      const state = findPda([STATE_SEEDS, id.toArrayLike(Buffer, "le", 8)]);
+      return {
+        id,
+        state,
+        pubsup: findPda([PUB_POOL_SEEDS, state.toBuffer()]),
+        reserve: findPda([RES_POOL_SEEDS, state.toBuffer()]),
+        burn: findPda([BUR_POOL_SEEDS, state.toBuffer()]),
+        vault: findPda([VAULT_SEEDS, state.toBuffer()]),
+        solVault: findPda([SOL_VAULT_SEEDS, state.toBuffer()]),
+        round: (index) => findPda([ROUND_SEED, state.toBuffer(), Buffer.from([index])]),
+        paymentMintInfo: (mint) => findPda([PAYMENT_MINT_SEED, state.toBuffer(), mint.toBuffer()]),
+        vesting: (round, buyer) => findPda([VEST_SEED, state.toBuffer(), Buffer.from([round]), buyer.toBuffer()]),
+        withdrawal: (pool) => findPda([WITHDRAWAL_SEED, state.toBuffer(), Buffer.from([pool])]),
+        allocation: (kind, beneficiary) => findPda([ALLOCATION_SEED, state.toBuffer(), Buffer.from([kind]), beneficiary.toBuffer()]),
+      };
+    }
+
+    async function bankTime(): Promise<number> {
+      return Number((await context.banksClient.getClock()).unixTimestamp);
+    }
+
+    // Moves the bank's clock to `timestamp`. Warping a slot also records a new
+    // blockhash, so a transaction sent before can be sent again afterwards.
+    async function warpTo(timestamp: number) {
+      const clock = await context.banksClient.getClock();
+      const slot = clock.slot + BigInt(1);
+      context.warpToSlot(slot);
+      context.setClock(new Clock(slot, clock.epochStartTimestamp, clock.epoch, clock.leaderScheduleEpoch, BigInt(timestamp)));
+    }
+
+    function setMint(mint: anchor.web3.PublicKey, decimals: number) {
+      const data = Buffer.alloc(MINT_SIZE);
+      MintLayout.encode({
+        mintAuthorityOption: 1,
+        mintAuthority: authority,
+        supply: BigInt(0),
+        decimals,
+        isInitialized: true,
+        freezeAuthorityOption: 0,
+        freezeAuthority: anchor.web3.PublicKey.default,
+      }, data);
+      context.setAccount(mint, { lamports: anchor.web3.LAMPORTS_PER_SOL, data, owner: TOKEN_PROGRAM_ID, executable: false });
+    }
+
+    // Sends a request straight to the bank and decodes the program events it
+    // logged; event listeners never fire on a bank
+    async function sendForEvents(request: { transaction(): Promise<anchor.web3.Transaction> }, signers: anchor.web3.Keypair[] = []) {
+      const transaction = await request.transaction();
+      transaction.recentBlockhash = (await context.banksClient.getLatestBlockhash())[0];
+      transaction.feePayer = authority;
+      transaction.sign(context.payer, ...signers);
+      const meta = await context.banksClient.processTransaction(transaction);
+      const parser = new anchor.EventParser(bank.programId, new anchor.BorshCoder(IDL));
+      return Array.from(parser.parseLogs(meta.logMessages));
+    }
+
+    // Fails unless `request` is rejected with the program error `name`
+    async function expectError(request: Promise<unknown>, name: string) {
+      const code = IDL.errors.find((error) => error.name === name).code;
+      try {
+        await request;
+      } catch (err) {
+        const message = `${err} ${(err.logs ?? []).join(" ")}`;
+        assert.ok(
+          err.code === code || err.error?.errorCode?.code === name || message.includes(`0x${code.toString(16)}`),
+          `Expected ${name}, got ${err}`,
+        );
+        return;
+      }
+      assert.fail(`Expected ${name}`);
+    }
+
+    async function initializeSale({ softCap = 0, hardCap = 0, burnPoolBps = 0, withdrawalDelay = 0 }: SaleOptions = {}): Promise<Sale> {
+      const sale = salePdas(new anchor.BN(nextSaleId++));
+      await bank.methods.initialize(sale.id, 5000, burnPoolBps, new anchor.BN(softCap), new anchor.BN(hardCap), new anchor.BN(withdrawalDelay)).accounts({
+        admin: authority,
+        mint: saleMint,
+        paymentMint: usdcMint,
+        state: sale.state,
+        pubsupPda: sale.pubsup,
+        reservePda: sale.reserve,
+        burnPda: sale.burn,
+        vaultPda: sale.vault,
+        solVault: sale.solVault,
+        paymentMintInfo: sale.paymentMintInfo(usdcMint),
+        systemProgram: anchor.web3.SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      }).rpc();
+      return sale;
+    }
+
+    function newWallet(): anchor.web3.Keypair {
+      const wallet = anchor.web3.Keypair.generate();
+      context.setAccount(wallet.publicKey, { lamports: 10 * anchor.web3.LAMPORTS_PER_SOL, data: Buffer.alloc(0), owner: anchor.web3.SystemProgram.programId, executable: false });
+      return wallet;
+    }
+
+    async function fund(mint: anchor.web3.PublicKey, owner: anchor.web3.PublicKey, amount: number): Promise<anchor.web3.PublicKey> {
+      const tokenAccount = getAssociatedTokenAddressSync(mint, owner, true);
+      const transaction = new anchor.web3.Transaction().add(
+        createAssociatedTokenAccountIdempotentInstruction(authority, tokenAccount, owner, mint),
+        createMintToInstruction(mint, tokenAccount, authority, amount),
+      );
+      await bankProvider.sendAndConfirm(transaction);
+      return tokenAccount;
+    }
+
+    async function tokenBalance(tokenAccount: anchor.web3.PublicKey): Promise<number> {
+      const account = await context.banksClient.getAccount(tokenAccount);
+      return account ? Number(AccountLayout.decode(account.data).amount) : 0;
+    }
+
+    async function lamports(address: anchor.web3.PublicKey): Promise<number> {
+      return Number(await context.banksClient.getBalance(address));
+    }
+
+    async function mintSupply(mint: anchor.web3.PublicKey): Promise<number> {
+      const account = await context.banksClient.getAccount(mint);
+      return Number(MintLayout.decode(account.data).supply);
+    }
+
+    // Initializes a sale, adds back-to-back day-long rounds at 1:1, distributes
+    // the supply and starts the first round
+    async function createSale(options: SaleOptions = {}): Promise<Sale> {
+      const sale = await initializeSale(options);
+      const rounds = options.rounds ?? [{}];
+      const start = await bankTime();
+      for (const [i, round] of rounds.entries()) {
+        await bank.methods.addRound({
+          allocationBps: 10000 / rounds.length,
+          price: { numerator: new anchor.BN(1), denominator: new anchor.BN(1) },
+          usdPrice: { numerator: new anchor.BN(round.usdPrice ?? 0), denominator: new anchor.BN(1) },
+          solPrice: { numerator: new anchor.BN(round.solPrice ?? 0), denominator: new anchor.BN(1) },
+          schedule: round.schedule ?? schedule(10000, 0, 0, 0),
+          startTime: new anchor.BN(start + i * day),
+          endTime: new anchor.BN(start + (i + 1) * day),
+          walletCap: new anchor.BN(0),
+          allowlistRoot: Array.from(round.allowlistRoot ?? Buffer.alloc(32)),
+          minContribution: new anchor.BN(round.minContribution ?? 0),
+          maxContribution: new anchor.BN(round.maxContribution ?? 0),
+        }).accounts({
+          authority,
+          state: sale.state,
+          previousRound: i > 0 ? sale.round(i - 1) : null,
+          round: sale.round(i),
+          systemProgram: anchor.web3.SystemProgram.programId,
+        }).rpc();
+      }
+      await bank.methods.dogdistribution(new anchor.BN(SALE_SUPPLY)).accounts({
+        admin: authority,
+        adminAta,
+        mint: saleMint,
+        state: sale.state,
+        pubsupPda: sale.pubsup,
+        pubsupAta: poolAta(sale.pubsup),
+        reservePda: sale.reserve,
+        reservePoolAta: poolAta(sale.reserve),
+        burnPda: sale.burn,
+        burnPoolAta: poolAta(sale.burn),
+        systemProgram: anchor.web3.SystemProgram.programId,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      }).preInstructions([createMintToInstruction(saleMint, adminAta, authority, SALE_SUPPLY)]).rpc();
+      await bank.methods.startRound(0).accounts({
+        authority,
+        state: sale.state,
+        previousRound: null,
+        nextRound: sale.round(0),
+        systemProgram: anchor.web3.SystemProgram.programId,
+        rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+      }).rpc();
+      return sale;
+    }
+
+    const buyRequest = (sale: Sale, buyer: anchor.web3.Keypair, payAmount: number, { round = 0, maxAllocation = 0, proof = [], minTokensOut = 0, paymentMint = usdcMint, priceFeed = null }: BuyOptions = {}) =>
+      bank.methods.purchasenow(new anchor.BN(payAmount), round, new anchor.BN(maxAllocation), proof.map((node) => Array.from(node)), new anchor.BN(minTokensOut)).accounts({
+        state: sale.state,
+        currentRound: sale.round(round),
+        mint: saleMint,
+        paymentMint,
+        paymentMintInfo: sale.paymentMintInfo(paymentMint),
+        priceFeed,
+        buyer: buyer.publicKey,
+        buyerPaymentMintAta: getAssociatedTokenAddressSync(paymentMint, buyer.publicKey),
+        vaultPda: sale.vault,
+        paymentVault: getAssociatedTokenAddressSync(paymentMint, sale.vault, true),
+        vesting: sale.vesting(round, buyer.publicKey),
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      }).signers([buyer]);
+
+    const buy = (...args: Parameters<typeof buyRequest>) => buyRequest(...args).rpc();
+
+    const startRoundRequest = (sale: Sale, round: number) =>
+      bank.methods.startRound(round).accounts({
+        authority,
+        state: sale.state,
+        previousRound: sale.round(round - 1),
+        nextRound: sale.round(round),
+        systemProgram: anchor.web3.SystemProgram.programId,
+        rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+      });
+
+    const startRound = (sale: Sale, round: number) => startRoundRequest(sale, round).rpc();
+
+    const finalize = (sale: Sale, lastRound = 0, { activeRound = lastRound, signer }: FinalizeOptions = {}) =>
+      bank.methods.finalizeSale().accounts({
+        authority: signer ? signer.publicKey : authority,
+        state: sale.state,
+        lastRound: sale.round(lastRound),
+        activeRound: sale.round(activeRound),
+      }).signers(signer ? [signer] : []).rpc();
+
+    const setTge = (sale: Sale, tgeTimestamp: number) =>
+      bank.methods.setTge(new anchor.BN(tgeTimestamp)).accounts({ authority, state: sale.state }).rpc();
+
+    const claim = (sale: Sale, buyer: anchor.web3.Keypair) =>
+      bank.methods.claim(0, new anchor.BN(0)).accounts({
+        claimant: buyer.publicKey,
+        state: sale.state,
+        pubsupPda: sale.pubsup,
+        pubsupAta: poolAta(sale.pubsup),
+        mint: saleMint,
+        vesting: sale.vesting(0, buyer.publicKey),
+        purchaseRound: sale.round(0),
+        claimantAta: poolAta(buyer.publicKey),
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      }).signers([buyer]).rpc();
+
+    // Claims across the buyer's vesting accounts in `rounds`
+    const claimAllRequest = (sale: Sale, buyer: anchor.web3.Keypair, rounds: number[]) =>
+      bank.methods.claimAll().accounts({
+        claimant: buyer.publicKey,
+        state: sale.state,
+        pubsupPda: sale.pubsup,
+        pubsupAta: poolAta(sale.pubsup),
+        mint: saleMint,
+        claimantAta: poolAta(buyer.publicKey),
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      }).remainingAccounts(rounds.flatMap((round) => [
+        { pubkey: sale.vesting(round, buyer.publicKey), isSigner: false, isWritable: true },
+        { pubkey: sale.round(round), isSigner: false, isWritable: false },
+      ])).signers([buyer]);
+
+    const claimAll = (sale: Sale, buyer: anchor.web3.Keypair, rounds: number[]) => claimAllRequest(sale, buyer, rounds).rpc();
+
+    const refund = (sale: Sale, buyer: anchor.web3.Keypair) =>
+      bank.methods.refund(0).accounts({
+        buyer: buyer.publicKey,
+        state: sale.state,
+        vesting: sale.vesting(0, buyer.publicKey),
+        purchaseRound: sale.round(0),
+        vaultPda: sale.vault,
+        paymentVault: getAssociatedTokenAddressSync(usdcMint, sale.vault, true),
+        buyerPaymentMintAta: getAssociatedTokenAddressSync(usdcMint, buyer.publicKey),
+        paymentMint: usdcMint,
+        paymentMintInfo: sale.paymentMintInfo(usdcMint),
+        solVault: sale.solVault,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      }).signers([buyer]).rpc();
+
+    // Withdrawals signed by `signer` pay out to its own sale-token account
+    const queueWithdrawal = (sale: Sale, pool: "public" | "reserve", amount: number, signer?: anchor.web3.Keypair) =>
+      bank.methods.queueWithdrawal(pool === "public" ? { public: {} } : { reserve: {} }, new anchor.BN(amount)).accounts({
+        authority: signer ? signer.publicKey : authority,
+        state: sale.state,
+        pendingWithdrawal: sale.withdrawal(pool === "public" ? 0 : 1),
+        recipient: signer ? poolAta(signer.publicKey) : adminAta,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      }).signers(signer ? [signer] : []).rpc();
+
+    const executeWithdrawal = (sale: Sale, pool: "public" | "reserve", signer?: anchor.web3.Keypair) =>
+      bank.methods.executeWithdrawal(pool === "public" ? { public: {} } : { reserve: {} }).accounts({
+        authority: signer ? signer.publicKey : authority,
+        state: sale.state,
+        pendingWithdrawal: sale.withdrawal(pool === "public" ? 0 : 1),
+        poolPda: pool === "public" ? sale.pubsup : sale.reserve,
+        poolAta: poolAta(pool === "public" ? sale.pubsup : sale.reserve),
+        recipient: signer ? poolAta(signer.publicKey) : adminAta,
+        mint: saleMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers(signer ? [signer] : []).rpc();
+
+    const createAllocation = (sale: Sale, kind: "team" | "advisors", beneficiary: anchor.web3.PublicKey, amount: number) =>
+      bank.methods.createAllocation(kind === "team" ? { team: {} } : { advisors: {} }, beneficiary, new anchor.BN(amount), schedule(5000, 0, 10 * day, day)).accounts({
+        admin: authority,
+        state: sale.state,
+        allocation: sale.allocation(kind === "team" ? 0 : 1, beneficiary),
+        systemProgram: anchor.web3.SystemProgram.programId,
+      }).rpc();
+
+    const claimAllocation = (sale: Sale, beneficiary: anchor.web3.Keypair) =>
+      bank.methods.claimAllocation({ team: {} }).accounts({
+        beneficiary: beneficiary.publicKey,
+        state: sale.state,
+        allocation: sale.allocation(0, beneficiary.publicKey),
+        reservePda: sale.reserve,
+        reserveAta: poolAta(sale.reserve),
+        beneficiaryAta: poolAta(beneficiary.publicKey),
+        mint: saleMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      }).signers([beneficiary]).rpc();
+
+    const burnUnsold = (sale: Sale, signer?: anchor.web3.Keypair) =>
+      bank.methods.burnUnsold().accounts({
+        authority: signer ? signer.publicKey : authority,
+        state: sale.state,
+        pubsupPda: sale.pubsup,
+        pubsupAta: poolAta(sale.pubsup),
+        burnPda: sale.burn,
+        burnPoolAta: poolAta(sale.burn),
+        mint: saleMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers(signer ? [signer] : []).rpc();
+
+    const withdrawProceeds = (sale: Sale) =>
+      bank.methods.withdrawProceeds().accounts({
+        authority,
+        state: sale.state,
+        vaultPda: sale.vault,
+        paymentVault: getAssociatedTokenAddressSync(usdcMint, sale.vault, true),
+        authorityPaymentAta: getAssociatedTokenAddressSync(usdcMint, authority),
+        paymentMint: usdcMint,
+        paymentMintInfo: sale.paymentMintInfo(usdcMint),
+        solVault: sale.solVault,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      }).rpc();
+
+    const cancelAllocation = (sale: Sale, beneficiary: anchor.web3.PublicKey) =>
+      bank.methods.cancelAllocation({ team: {} }, beneficiary).accounts({
+        admin: authority,
+        state: sale.state,
+        allocation: sale.allocation(0, beneficiary),
+      }).rpc();
+
+    const buyWithSol = (sale: Sale, buyer: anchor.web3.Keypair, lamports: number) =>
+      bank.methods.purchaseWithSol(new anchor.BN(lamports), 0, new anchor.BN(0), [], new anchor.BN(0)).accounts({
+        state: sale.state,
+        currentRound: sale.round(0),
+        buyer: buyer.publicKey,
+        vesting: sale.vesting(0, buyer.publicKey),
+        solVault: sale.solVault,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      }).signers([buyer]).rpc();
+
+    const setPaused = (sale: Sale, paused: number, signer?: anchor.web3.Keypair) =>
+      bank.methods.setPaused(paused).accounts({
+        authority: signer ? signer.publicKey : authority,
+        state: sale.state,
+      }).signers(signer ? [signer] : []).rpc();
+
+    before(async () => {
+      context = await startAnchor("", [], []);
+      bankProvider = new BankrunProvider(context);
+      bank = new Program<Sepawithdraw>(IDL, program.programId, bankProvider);
+      authority = bankProvider.publicKey;
+      setMint(saleMint, 6);
+      setMint(usdcMint, 6);
+      adminAta = await fund(saleMint, authority, 0);
+    });
+
+    describe("oracle pricing", () => {
+      const staleFeed = anchor.web3.Keypair.generate().publicKey;
+      const wideFeed = anchor.web3.Keypair.generate().publicKey;
+      const foreignFeed = anchor.web3.Keypair.generate().publicKey;
+      const maxPriceAge = 60 * 60;
+      let sale: Sale;
+
+      const setFeed = (address: anchor.web3.PublicKey, data: Buffer, owner = PYTH_PROGRAM_ID) =>
+        context.setAccount(address, { lamports: anchor.web3.LAMPORTS_PER_SOL, data, owner, executable: false });
+
+      const setPriceFeed = (priceFeed: anchor.web3.PublicKey, target = sale, paymentMint = usdcMint) =>
+        bank.methods.setPriceFeed(new anchor.BN(maxPriceAge), 100).accounts({
+          authority,
+          state: target.state,
+          paymentMintInfo: target.paymentMintInfo(paymentMint),
+          priceFeed,
+        }).rpc();
+
+      before(async () => {
+        // $150.00 +/- $0.05, published at the bank's current time
+        const now = BigInt(await bankTime());
+        const feed = { price: BigInt(150_00000000), conf: BigInt(5000000), expo: -8, timestamp: now };
+        setFeed(MOCK_PRICE_FEED, encodePriceFeed(feed));
+        setFeed(staleFeed, encodePriceFeed({ ...feed, timestamp: now - BigInt(2 * maxPriceAge) }));
+        setFeed(wideFeed, encodePriceFeed({ ...feed, conf: BigInt(15_00000000) }));
+        setFeed(foreignFeed, encodePriceFeed(feed), anchor.web3.SystemProgram.programId);
+        sale = await initializeSale();
+      });
+
+      it("Prices a payment mint from the mock oracle feed", async () => {
+        const tx = await setPriceFeed(MOCK_PRICE_FEED);
+        log("Price feed set. Signature:", tx);
+
+        const info = await bank.account.paymentMint.fetch(sale.paymentMintInfo(usdcMint));
+        assert.ok(info.priceFeed.equals(MOCK_PRICE_FEED), "Price feed should be registered");
+        assert.equal(info.maxPriceAge.toString(), maxPriceAge.toString(), "Max price age should be set");
+      });
+
+      it("Rejects a stale price feed", async () => {
+        await expectError(setPriceFeed(staleFeed), "StaleOraclePrice");
+      });
+
+      it("Rejects a price feed with a wide confidence interval", async () => {
+        await expectError(setPriceFeed(wideFeed), "OracleConfidenceTooWide");
+      });
+
+      it("Rejects a price feed the Pyth program doesn't own", async () => {
+        await expectError(setPriceFeed(foreignFeed), "InvalidPriceFeed");
+      });
+
+      it("Buys at the oracle rate with the registered feed only", async () => {
+        // $1 per token against a 9-decimal mint at $150: 10_000_000 base units buy 1.5 tokens
+        const oracleSale = await createSale({ rounds: [{ usdPrice: 1 }] });
+        const wrappedMint = anchor.web3.Keypair.generate().publicKey;
+        setMint(wrappedMint, 9);
+        await bank.methods.addPaymentMint().accounts({
+          authority,
+          state: oracleSale.state,
+          paymentMint: wrappedMint,
+          paymentMintInfo: oracleSale.paymentMintInfo(wrappedMint),
+          systemProgram: anchor.web3.SystemProgram.programId,
+        }).rpc();
+        await setPriceFeed(MOCK_PRICE_FEED, oracleSale, wrappedMint);
+        const buyer = newWallet();
+        const buyerAta = await fund(wrappedMint, buyer.publicKey, 10_000_000);
+
+        await expectError(buy(oracleSale, buyer, 10_000_000, { paymentMint: wrappedMint, priceFeed: staleFeed }), "InvalidPriceFeed");
+        await buy(oracleSale, buyer, 10_000_000, { paymentMint: wrappedMint, priceFeed: MOCK_PRICE_FEED });
+
+        const vesting = await bank.account.vesting.fetch(oracleSale.vesting(0, buyer.publicKey));
+        assert.equal(vesting.purchases[0].amount.toString(), "1500000", "Tokens should be priced at the oracle rate");
+        assert.equal(await tokenBalance(buyerAta), 0, "The whole payment should be taken");
+      });
+    });
+
+    describe("sale lifecycle", () => {
+      it("Refunds buyers when the sale misses its soft cap", async () => {
+        const sale = await createSale({ softCap: 5_000_000 });
+        const buyer = newWallet();
+        const buyerAta = await fund(usdcMint, buyer.publicKey, 1_000_000);
+        await buy(sale, buyer, 1_000_000);
+        assert.equal(await tokenBalance(buyerAta), 0, "Payment should be escrowed");
+
+        await finalize(sale);
+        let state = await bank.account.state.fetch(sale.state);
+        assert.deepEqual(state.status, { refunding: {} }, "Sale below its soft cap should refund");
+
+        await refund(sale, buyer);
+        assert.equal(await tokenBalance(buyerAta), 1_000_000, "Buyer should get their payment back");
+        state = await bank.account.state.fetch(sale.state);
+        assert.equal(state.totalOwedToBuyers.toString(), "0", "Refunded tokens should no longer be owed");
+        const round = await bank.account.round.fetch(sale.round(0));
+        assert.equal(round.tokensSold.toString(), "0", "Refunded tokens should come off the round's sales");
+      });
+
+      it("Partially fills the purchase that reaches the hard cap", async () => {
+        const sale = await createSale({ hardCap: 3_000_000 });
+        const first = newWallet();
+        await fund(usdcMint, first.publicKey, 2_000_000);
+        await buy(sale, first, 2_000_000);
+
+        const second = newWallet();
+        const secondAta = await fund(usdcMint, second.publicKey, 2_000_000);
+        await buy(sale, second, 2_000_000);
+
+        assert.equal(await tokenBalance(secondAta), 1_000_000, "Only the fill up to the hard cap should be charged");
+        const vesting = await bank.account.vesting.fetch(sale.vesting(0, second.publicKey));
+        assert.equal(vesting.totalPurchased.toString(), "1000000", "Purchase should be truncated to the hard cap");
+        const state = await bank.account.state.fetch(sale.state);
+        assert.equal(state.totalRaised.toString(), "3000000", "Sale should raise exactly its hard cap");
+        assert.deepEqual(state.status, { finalized: {} }, "Reaching the hard cap should close the sale");
+      });
+
+      it("Vests purchases linearly from TGE after the cliff", async () => {
+        const sale = await createSale({ rounds: [{ schedule: schedule(1000, 10 * day, 100 * day, day) }] });
+        const buyer = newWallet();
+        await fund(usdcMint, buyer.publicKey, 1_000_000);
+        await buy(sale, buyer, 1_000_000);
+        const tge = (await bankTime()) + day;
+        await expectError(setTge(sale, tge + day), "SaleNotFinalized");
+        await finalize(sale);
+        await setTge(sale, tge);
+        await expectError(setTge(sale, tge + 2 * day), "TgeAlreadySet");
+
+        await expectError(claim(sale, buyer), "TgeNotReached");
+        await warpTo(tge);
+        await claim(sale, buyer);
+        assert.equal(await tokenBalance(poolAta(buyer.publicKey)), 100_000, "TGE unlock should be claimable at TGE");
+        await warpTo(tge + 5 * day);
+        await expectError(claim(sale, buyer), "NothingToClaim");
+
+        // Ten whole release intervals past the cliff
+        await warpTo(tge + 20 * day + 60 * 60);
+        await claim(sale, buyer);
+        assert.equal(await tokenBalance(poolAta(buyer.publicKey)), 190_000, "A tenth of the rest should have vested");
+      });
+
+      it("Claims every round's purchases at once", async () => {
+        const sale = await createSale({ rounds: [{}, {}] });
+        const buyer = newWallet();
+        await fund(usdcMint, buyer.publicKey, 3_000_000);
+        await buy(sale, buyer, 1_000_000);
+        await startRound(sale, 1);
+        await warpTo((await bankTime()) + day);
+        await buy(sale, buyer, 2_000_000, { round: 1 });
+        await finalize(sale, 1);
+        await setTge(sale, await bankTime());
+
+        // A repeated pair is only claimed once
+        await claimAll(sale, buyer, [0, 1, 0]);
+        assert.equal(await tokenBalance(poolAta(buyer.publicKey)), 3_000_000, "Both rounds' purchases should be claimed");
+        const state = await bank.account.state.fetch(sale.state);
+        assert.equal(state.totalOwedToBuyers.toString(), "0", "Claimed tokens should no longer be owed");
+      });
+
+      it("Limits allowlisted rounds to proven wallets and allocations", async () => {
+        const listed = newWallet();
+        const other = newWallet();
+        const outsider = newWallet();
+        const listedLeaf = allowlistLeaf(listed.publicKey, 2_000_000);
+        const otherLeaf = allowlistLeaf(other.publicKey, 1_000_000);
+        const sale = await createSale({ rounds: [{ allowlistRoot: hashPair(listedLeaf, otherLeaf) }] });
+        await fund(usdcMint, listed.publicKey, 3_000_000);
+        await fund(usdcMint, outsider.publicKey, 1_000_000);
+
+        await buy(sale, listed, 1_500_000, { maxAllocation: 2_000_000, proof: [otherLeaf] });
+        await expectError(buy(sale, listed, 600_000, { maxAllocation: 2_000_000, proof: [otherLeaf] }), "AllocationExceeded");
+        await expectError(buy(sale, outsider, 1_000_000, { maxAllocation: 2_000_000, proof: [otherLeaf] }), "NotAllowlisted");
+
+        const vesting = await bank.account.vesting.fetch(sale.vesting(0, listed.publicKey));
+        assert.equal(vesting.totalPurchased.toString(), "1500000", "Only the purchase within the allocation should land");
+      });
+
+      it("Enforces per-wallet contribution limits", async () => {
+        const sale = await createSale({ rounds: [{ minContribution: 100_000, maxContribution: 1_000_000 }] });
+        const buyer = newWallet();
+        await fund(usdcMint, buyer.publicKey, 2_000_000);
+
+        await expectError(buy(sale, buyer, 50_000), "BelowMinContribution");
+        await buy(sale, buyer, 800_000);
+        await expectError(buy(sale, buyer, 300_000), "ExceedsMaxContribution");
+
+        const vesting = await bank.account.vesting.fetch(sale.vesting(0, buyer.publicKey));
+        assert.equal(vesting.totalContributed.toString(), "800000", "Only the purchase within the limits should land");
+      });
+
+      it("Rejects purchases that fill below the buyer's minimum", async () => {
+        const sale = await createSale({ hardCap: 1_000_000 });
+        const first = newWallet();
+        await fund(usdcMint, first.publicKey, 800_000);
+        await buy(sale, first, 800_000);
+
+        // Only 200,000 tokens remain under the hard cap
+        const second = newWallet();
+        await fund(usdcMint, second.publicKey, 500_000);
+        await expectError(buy(sale, second, 500_000, { minTokensOut: 500_000 }), "SlippageExceeded");
+        await buy(sale, second, 500_000, { minTokensOut: 200_000 });
+
+        const vesting = await bank.account.vesting.fetch(sale.vesting(0, second.publicKey));
+        assert.equal(vesting.totalPurchased.toString(), "200000", "Partial fill should land when it meets the minimum");
+      });
+
+      it("Prices added payment mints per round", async () => {
+        const sale = await createSale();
+        const eurcMint = anchor.web3.Keypair.generate().publicKey;
+        setMint(eurcMint, 6);
+        await bank.methods.addPaymentMint().accounts({
+          authority,
+          state: sale.state,
+          paymentMint: eurcMint,
+          paymentMintInfo: sale.paymentMintInfo(eurcMint),
+          systemProgram: anchor.web3.SystemProgram.programId,
+        }).rpc();
+        const buyer = newWallet();
+        await fund(eurcMint, buyer.publicKey, 3_000_000);
+        await expectError(buy(sale, buyer, 1_000_000, { paymentMint: eurcMint }), "PaymentMintNotPriced");
+
+        await bank.methods.setMintPrice(0, { numerator: new anchor.BN(2), denominator: new anchor.BN(1) }).accounts({
+          authority,
+          state: sale.state,
+          roundAccount: sale.round(0),
+          paymentMintInfo: sale.paymentMintInfo(eurcMint),
+        }).rpc();
+        await buy(sale, buyer, 2_000_000, { paymentMint: eurcMint });
+
+        const vesting = await bank.account.vesting.fetch(sale.vesting(0, buyer.publicKey));
+        assert.equal(vesting.purchases[0].amount.toString(), "1000000", "Tokens should be priced at the mint's round price");
+        const info = await bank.account.paymentMint.fetch(sale.paymentMintInfo(eurcMint));
+        assert.equal(info.totalRaised.toString(), "2000000", "Proceeds should be tracked per mint");
+        const state = await bank.account.state.fetch(sale.state);
+        assert.equal(state.totalRaised.toString(), "1000000", "Sale totals should be valued at the reference price");
+      });
+
+      it("Timelocks pool withdrawals", async () => {
+        const sale = await createSale({ withdrawalDelay: 2 * day });
+        const balance = await tokenBalance(adminAta);
+
+        await queueWithdrawal(sale, "reserve", 1_000_000);
+        await expectError(executeWithdrawal(sale, "reserve"), "WithdrawalLocked");
+        await warpTo((await bankTime()) + 2 * day);
+        await executeWithdrawal(sale, "reserve");
+        assert.equal((await tokenBalance(adminAta)) - balance, 1_000_000, "Withdrawal should execute after the delay");
+        const state = await bank.account.state.fetch(sale.state);
+        assert.equal(state.reserveSupply.toString(), "4000000", "Reserve supply should be reduced");
+      });
+
+      it("Keeps the public pool locked until the sale closes", async () => {
+        const sale = await createSale();
+        await queueWithdrawal(sale, "public", 1_000_000);
+        await expectError(executeWithdrawal(sale, "public"), "SaleNotClosed");
+
+        await finalize(sale);
+        const balance = await tokenBalance(adminAta);
+        await executeWithdrawal(sale, "public");
+        assert.equal((await tokenBalance(adminAta)) - balance, 1_000_000, "Public withdrawal should execute once the sale closes");
+      });
+
+      it("Vests reserve allocations to their beneficiaries", async () => {
+        const sale = await createSale();
+        const beneficiary = newWallet();
+        await createAllocation(sale, "team", beneficiary.publicKey, 1_000_000);
+        await expectError(createAllocation(sale, "advisors", beneficiary.publicKey, SALE_SUPPLY), "InsufficientReserve");
+        await finalize(sale);
+        const tge = await bankTime();
+        await setTge(sale, tge);
+
+        await claimAllocation(sale, beneficiary);
+        assert.equal(await tokenBalance(poolAta(beneficiary.publicKey)), 500_000, "TGE unlock should be claimable at TGE");
+        await warpTo(tge + 5 * day);
+        await claimAllocation(sale, beneficiary);
+        assert.equal(await tokenBalance(poolAta(beneficiary.publicKey)), 750_000, "Half the rest should have vested");
+
+        const state = await bank.account.state.fetch(sale.state);
+        assert.equal(state.reserveOwed.toString(), "250000", "Unclaimed allocation should still be owed");
+        assert.equal(state.reserveSupply.toString(), "4000000", "Allocation should come out of the reserve supply");
+      });
+
+      it("Burns the burn pool, then the unsold public supply once the sale closes", async () => {
+        const sale = await createSale({ burnPoolBps: 1000 });
+        await expectError(burnUnsold(sale, newWallet()), "Unauthorized");
+        const operator = newWallet();
+        await bank.methods.grantRole({ operator: {} }, operator.publicKey).accounts({ admin: authority, state: sale.state }).rpc();
+        const supply = await mintSupply(saleMint);
+
+        await burnUnsold(sale, operator);
+        assert.equal(await tokenBalance(poolAta(sale.burn)), 0, "Burn pool should be burned");
+        assert.equal(await tokenBalance(poolAta(sale.pubsup)), 5_000_000, "Round tokens should not burn while the sale runs");
+
+        await finalize(sale);
+        await burnUnsold(sale);
+        assert.equal(await tokenBalance(poolAta(sale.pubsup)), 0, "Unsold public supply should be burned");
+        assert.equal(supply - (await mintSupply(saleMint)), 6_000_000, "Burned tokens should leave the supply");
+        const state = await bank.account.state.fetch(sale.state);
+        assert.equal(state.burnedSupply.toString(), "6000000", "Burned supply should be tracked");
+      });
+
+      it("Rolls unsold round tokens into the next round", async () => {
+        const sale = await createSale({ rounds: [{}, {}] });
+        await bank.methods.setUnsoldPolicy({ rollover: {} }).accounts({ authority, state: sale.state }).rpc();
+        const buyer = newWallet();
+        await fund(usdcMint, buyer.publicKey, 1_000_000);
+        await buy(sale, buyer, 1_000_000);
+
+        await startRound(sale, 1);
+        const round = await bank.account.round.fetch(sale.round(1));
+        assert.equal(round.balance.toString(), "4000000", "Round 1 should get its share plus round 0's unsold tokens");
+      });
+
+      it("Returns unsold round tokens to the reserve", async () => {
+        const sale = await createSale();
+        await bank.methods.setUnsoldPolicy({ returnToReserve: {} }).accounts({ authority, state: sale.state }).rpc();
+        await finalize(sale);
+        let state = await bank.account.state.fetch(sale.state);
+        assert.equal(state.unsoldToReserve.toString(), "5000000", "Unsold round tokens should be set aside for the reserve");
+
+        await bank.methods.sweepUnsoldToReserve().accounts({
+          authority,
+          state: sale.state,
+          pubsupPda: sale.pubsup,
+          pubsupAta: poolAta(sale.pubsup),
+          reservePda: sale.reserve,
+          reserveAta: poolAta(sale.reserve),
+          mint: saleMint,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        }).rpc();
+        assert.equal(await tokenBalance(poolAta(sale.reserve)), 10_000_000, "Unsold tokens should move to the reserve pool");
+        state = await bank.account.state.fetch(sale.state);
+        assert.equal(state.reserveSupply.toString(), "10000000", "Reserve supply should include the swept tokens");
+      });
+
+      it("Lets anyone finalize after the last round's window when later rounds never started", async () => {
+        const sale = await createSale({ rounds: [{}, {}] });
+        const buyer = newWallet();
+        await fund(usdcMint, buyer.publicKey, 1_000_000);
+        await buy(sale, buyer, 1_000_000);
+
+        // Round 1 is never started, so round 0 is still the active round
+        const stranger = newWallet();
+        await expectError(finalize(sale, 1, { activeRound: 0, signer: stranger }), "Unauthorized");
+        const lastRound = await bank.account.round.fetch(sale.round(1));
+        await warpTo(lastRound.endTime.toNumber() + 1);
+        await finalize(sale, 1, { activeRound: 0, signer: stranger });
+
+        const state = await bank.account.state.fetch(sale.state);
+        assert.deepEqual(state.status, { finalized: {} }, "Sale should be finalized");
+        assert.equal(state.pubSupply.toString(), "4000000", "Round 0's unsold tokens and round 1's share should return to the public supply");
+        const round = await bank.account.round.fetch(sale.round(0));
+        assert.isFalse(round.active, "The active round should be ended");
+
+        const proceeds = await fund(usdcMint, authority, 0);
+        const balance = await tokenBalance(proceeds);
+        await withdrawProceeds(sale);
+        assert.equal((await tokenBalance(proceeds)) - balance, 1_000_000, "Proceeds should be withdrawable");
+      });
+
+      it("Lets a non-operator close a sale that missed its soft cap", async () => {
+        const sale = await createSale({ softCap: 5_000_000 });
+        const buyer = newWallet();
+        const buyerAta = await fund(usdcMint, buyer.publicKey, 1_000_000);
+        await buy(sale, buyer, 1_000_000);
+
+        const round = await bank.account.round.fetch(sale.round(0));
+        await warpTo(round.endTime.toNumber() + 1);
+        await finalize(sale, 0, { signer: buyer });
+        const state = await bank.account.state.fetch(sale.state);
+        assert.deepEqual(state.status, { refunding: {} }, "Sale below its soft cap should refund");
+
+        await refund(sale, buyer);
+        assert.equal(await tokenBalance(buyerAta), 1_000_000, "Buyer should get their payment back");
+      });
+
+      it("Returns unvested allocations to the reserve when the sale refunds", async () => {
+        const sale = await createSale({ softCap: 5_000_000 });
+        const beneficiary = newWallet();
+        await createAllocation(sale, "team", beneficiary.publicKey, 1_000_000);
+        await finalize(sale);
+        await expectError(claimAllocation(sale, beneficiary), "TgeNotReached");
+
+        await cancelAllocation(sale, beneficiary.publicKey);
+        const state = await bank.account.state.fetch(sale.state);
+        assert.equal(state.reserveOwed.toString(), "0", "Cancelled allocation should no longer be owed");
+        assert.equal(state.reserveSupply.toString(), "5000000", "Unvested tokens should return to the reserve supply");
+        const allocation = await bank.account.allocation.fetch(sale.allocation(0, beneficiary.publicKey));
+        assert.equal(allocation.amount.toString(), "0", "Nothing had vested before TGE");
+        await warpTo((await bankTime()) + 1);
+        await expectError(cancelAllocation(sale, beneficiary.publicKey), "AllocationFullyVested");
+
+        const balance = await tokenBalance(adminAta);
+        await queueWithdrawal(sale, "reserve", 5_000_000);
+        await executeWithdrawal(sale, "reserve");
+        assert.equal((await tokenBalance(adminAta)) - balance, 5_000_000, "Returned tokens should be withdrawable from the reserve");
+      });
+
+      it("Escrows SOL purchases in the SOL vault", async () => {
+        const sale = await createSale({ rounds: [{ solPrice: 1000 }] });
+        const buyer = newWallet();
+        const vaultBalance = await lamports(sale.solVault);
+        await buyWithSol(sale, buyer, anchor.web3.LAMPORTS_PER_SOL);
+
+        assert.equal((await lamports(sale.solVault)) - vaultBalance, anchor.web3.LAMPORTS_PER_SOL, "Lamports should reach the SOL vault");
+        const vesting = await bank.account.vesting.fetch(sale.vesting(0, buyer.publicKey));
+        assert.equal(vesting.purchases[0].amount.toString(), "1000000", "Tokens should be priced at the round's SOL price");
+        const state = await bank.account.state.fetch(sale.state);
+        assert.equal(state.totalRaisedSol.toString(), anchor.web3.LAMPORTS_PER_SOL.toString(), "SOL raised should be tracked");
+
+        const splOnly = await createSale();
+        await expectError(buyWithSol(splOnly, buyer, anchor.web3.LAMPORTS_PER_SOL), "SolPaymentsDisabled");
+      });
+
+      it("Refunds SOL purchases from the SOL vault", async () => {
+        const sale = await createSale({ softCap: 5_000_000, rounds: [{ solPrice: 1000 }] });
+        const buyer = newWallet();
+        const vaultBalance = await lamports(sale.solVault);
+        await buyWithSol(sale, buyer, anchor.web3.LAMPORTS_PER_SOL);
+        await finalize(sale);
+
+        // Refunds go through a payment mint's accounts even when only SOL was paid
+        await fund(usdcMint, sale.vault, 0);
+        await fund(usdcMint, buyer.publicKey, 0);
+        const buyerBalance = await lamports(buyer.publicKey);
+        await refund(sale, buyer);
+        assert.equal((await lamports(buyer.publicKey)) - buyerBalance, anchor.web3.LAMPORTS_PER_SOL, "Buyer should get their lamports back");
+        assert.equal(await lamports(sale.solVault), vaultBalance, "SOL vault should be back to its rent-exempt balance");
+      });
+
+      it("Sweeps SOL proceeds down to the vault's rent-exempt balance", async () => {
+        const sale = await createSale({ rounds: [{ solPrice: 1000 }] });
+        const buyer = newWallet();
+        const vaultBalance = await lamports(sale.solVault);
+        await fund(usdcMint, buyer.publicKey, 1_000_000);
+        await buy(sale, buyer, 1_000_000);
+        await buyWithSol(sale, buyer, anchor.web3.LAMPORTS_PER_SOL);
+        await finalize(sale);
+
+        await withdrawProceeds(sale);
+        assert.equal(await lamports(sale.solVault), vaultBalance, "SOL vault should keep only its rent-exempt balance");
+      });
+
+      it("Hands the sale to a new admin in two steps", async () => {
+        const sale = await createSale();
+        const buyer = newWallet();
+        await fund(usdcMint, buyer.publicKey, 1_000_000);
+        await buy(sale, buyer, 1_000_000);
+
+        const newAdmin = newWallet();
+        await bank.methods.proposeAdmin(newAdmin.publicKey).accounts({ admin: authority, state: sale.state }).rpc();
+        const impostor = newWallet();
+        await expectError(
+          bank.methods.acceptAdmin().accounts({ newAdmin: impostor.publicKey, state: sale.state }).signers([impostor]).rpc(),
+          "Unauthorized",
+        );
+        await bank.methods.acceptAdmin().accounts({ newAdmin: newAdmin.publicKey, state: sale.state }).signers([newAdmin]).rpc();
+        const state = await bank.account.state.fetch(sale.state);
+        assert.ok(state.admin.equals(newAdmin.publicKey), "New admin should take over");
+        await expectError(finalize(sale), "Unauthorized");
+
+        // The pool PDAs are derived from the sale, not the admin, so they still sign
+        const recipient = await fund(saleMint, newAdmin.publicKey, 0);
+        await queueWithdrawal(sale, "reserve", 1_000_000, newAdmin);
+        await executeWithdrawal(sale, "reserve", newAdmin);
+        assert.equal(await tokenBalance(recipient), 1_000_000, "Reserve pool should pay the new admin");
+
+        await finalize(sale, 0, { signer: newAdmin });
+        await bank.methods.setTge(new anchor.BN(await bankTime())).accounts({ authority: newAdmin.publicKey, state: sale.state }).signers([newAdmin]).rpc();
+        await claim(sale, buyer);
+        assert.equal(await tokenBalance(poolAta(buyer.publicKey)), 1_000_000, "Public pool should still pay buyers");
+      });
+
+      it("Pauses purchases without blocking claims", async () => {
+        const sale = await createSale();
+        const buyer = newWallet();
+        await fund(usdcMint, buyer.publicKey, 2_000_000);
+        await buy(sale, buyer, 1_000_000);
+
+        await expectError(setPaused(sale, PAUSE_PURCHASES, newWallet()), "Unauthorized");
+        await expectError(setPaused(sale, 1 << 3), "InvalidPauseFlags");
+        const pauser = newWallet();
+        await bank.methods.grantRole({ pauser: {} }, pauser.publicKey).accounts({ admin: authority, state: sale.state }).rpc();
+        await setPaused(sale, PAUSE_PURCHASES, pauser);
+        await expectError(buy(sale, buyer, 500_000), "SalePaused");
+
+        await finalize(sale);
+        await setTge(sale, await bankTime());
+        await claim(sale, buyer);
+        assert.equal(await tokenBalance(poolAta(buyer.publicKey)), 1_000_000, "Claims should go through while purchases are paused");
+
+        await setPaused(sale, PAUSE_CLAIMS | PAUSE_REFUNDS, pauser);
+        const state = await bank.account.state.fetch(sale.state);
+        assert.equal(state.paused, PAUSE_CLAIMS | PAUSE_REFUNDS, "Pause flags should be replaced");
+      });
+
+      it("Emits events for round starts, purchases and claims", async () => {
+        const sale = await createSale({ rounds: [{}, {}] });
+        const buyer = newWallet();
+        await fund(usdcMint, buyer.publicKey, 1_000_000);
+
+        let events = await sendForEvents(startRoundRequest(sale, 1));
+        const started = events.find((event) => event.name === "RoundStarted");
+        assert.ok(started, "RoundStarted should be emitted");
+        assert.equal(started.data.round, 1, "RoundStarted should name the round");
+        assert.equal(started.data.allocation.toString(), "2500000", "RoundStarted should carry the round's allocation");
+        assert.ok(events.some((event) => event.name === "RoundEnded"), "The previous round should end");
+
+        await warpTo((await bankTime()) + day);
+        events = await sendForEvents(buyRequest(sale, buyer, 1_000_000, { round: 1 }), [buyer]);
+        const purchased = events.find((event) => event.name === "TokensPurchased");
+        assert.ok(purchased, "TokensPurchased should be emitted");
+        assert.ok(purchased.data.buyer.equals(buyer.publicKey), "TokensPurchased should name the buyer");
+        assert.equal(purchased.data.tokens.toString(), "1000000", "TokensPurchased should carry the tokens bought");
+        assert.equal(purchased.data.totalRaised.toString(), "1000000", "TokensPurchased should carry the running total");
+
+        await finalize(sale, 1);
+        await setTge(sale, await bankTime());
+        events = await sendForEvents(claimAllRequest(sale, buyer, [1]), [buyer]);
+        const claimed = events.find((event) => event.name === "AllClaimed");
+        assert.ok(claimed, "AllClaimed should be emitted");
+        assert.equal(claimed.data.total.toString(), "1000000", "AllClaimed should carry the claimed total");
+        assert.equal(claimed.data.purchases.toString(), "1", "AllClaimed should count the purchases claimed");
+      });
+    });
+  });
 });